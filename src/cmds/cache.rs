@@ -0,0 +1,329 @@
+use crate::ffmpeg::{
+    encoder::SEGMENT_EXTENSION,
+    ffmpeg_version,
+    loudness::{measure_loudness, Loudness},
+};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use comfy_table::Table;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, clap::Args)]
+pub struct CacheDirArgs {
+    /// rendered segments, reused while their definition is unchanged
+    #[arg(long, env = "MIXXX_CACHE_DIR", default_value = ".mixxx_cache")]
+    pub cache_dir: PathBuf,
+}
+
+#[derive(Debug, clap::Parser)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum CacheCommand {
+    /// list rendered segments and loudness measurements
+    Ls(CacheDirArgs),
+    /// remove segments not used for a while
+    Gc(GcArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct GcArgs {
+    /// days since the last use
+    #[arg(long, default_value_t = 30)]
+    days: u64,
+    /// remove every segment
+    #[arg(long)]
+    all: bool,
+    #[command(flatten)]
+    dir: CacheDirArgs,
+}
+
+/// what a segment was rendered from, stored next to it
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub id: String,
+    pub sources: Vec<PathBuf>,
+    pub definition: String,
+    pub ffmpeg: String,
+}
+
+/// extension of cached loudness measurements
+const LOUDNESS_EXTENSION: &str = "loudness";
+
+/// a measured source file
+#[derive(Debug, Serialize, Deserialize)]
+struct LoudnessEntry {
+    source: PathBuf,
+    loudness: Loudness,
+}
+
+/// identifies a source file by path, size and modification time without reading it
+fn source_stamp(path: &Path) -> Result<String> {
+    let metadata =
+        std::fs::metadata(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    let modified = metadata
+        .modified()?
+        .duration_since(SystemTime::UNIX_EPOCH)?;
+    Ok(format!(
+        "{} {} {}",
+        path.display(),
+        metadata.len(),
+        modified.as_nanos()
+    ))
+}
+
+/// hex sha256 of the ffmpeg version, source stamps and definition
+fn hash_key(ffmpeg: &str, stamps: &[String], definition: &str) -> String {
+    let mut hasher = Sha256::new();
+    for part in std::iter::once(ffmpeg)
+        .chain(stamps.iter().map(|stamp| stamp.as_str()))
+        .chain([definition])
+    {
+        hasher.update(part.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+impl CacheEntry {
+    pub fn key(&self) -> Result<String> {
+        let stamps = self
+            .sources
+            .iter()
+            .map(|source| source_stamp(source))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(hash_key(&self.ffmpeg, &stamps, &self.definition))
+    }
+}
+
+/// rendered segments named by the key of their `CacheEntry`
+#[derive(Debug)]
+pub struct RenderCache {
+    dir: PathBuf,
+    ffmpeg: String,
+}
+
+impl RenderCache {
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            // listed in a file list of another directory
+            dir: dir.canonicalize()?,
+            ffmpeg: ffmpeg_version()?,
+        })
+    }
+
+    pub fn entry(&self, id: String, sources: Vec<PathBuf>, definition: String) -> CacheEntry {
+        CacheEntry {
+            id,
+            sources,
+            definition,
+            ffmpeg: self.ffmpeg.clone(),
+        }
+    }
+
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, SEGMENT_EXTENSION))
+    }
+
+    /// marks a rendered segment as used for `cache gc`
+    pub fn touch(&self, key: &str) -> Result<()> {
+        touch(&self.path(key))
+    }
+
+    /// loudness of `source`, measured again once the file or ffmpeg changes
+    pub fn loudness(&self, source: &Path) -> Result<Loudness> {
+        let key = hash_key(&self.ffmpeg, &[source_stamp(source)?], "loudnorm");
+        let path = self.dir.join(format!("{}.{}", key, LOUDNESS_EXTENSION));
+        if path.exists() {
+            touch(&path)?;
+            let entry: LoudnessEntry = serde_json::from_str(&std::fs::read_to_string(&path)?)?;
+            return Ok(entry.loudness);
+        }
+        let entry = LoudnessEntry {
+            source: source.to_path_buf(),
+            loudness: measure_loudness(source)?,
+        };
+        std::fs::write(&path, serde_json::to_string_pretty(&entry)?)?;
+        Ok(entry.loudness)
+    }
+
+    /// calls `render` with a partial file, kept as the segment of `key` on success
+    pub fn insert(
+        &self,
+        key: &str,
+        entry: &CacheEntry,
+        render: impl FnOnce(&Path) -> Result<()>,
+    ) -> Result<()> {
+        let partial = self.dir.join(format!("{}.part.{}", key, SEGMENT_EXTENSION));
+        render(&partial)?;
+        std::fs::write(
+            self.dir.join(format!("{}.json", key)),
+            serde_json::to_string_pretty(entry)?,
+        )?;
+        std::fs::rename(&partial, self.path(key))?;
+        Ok(())
+    }
+}
+
+fn touch(path: &Path) -> Result<()> {
+    File::options()
+        .append(true)
+        .open(path)?
+        .set_modified(SystemTime::now())?;
+    Ok(())
+}
+
+/// a segment or measurement, or a leftover file when `label` is missing
+struct CachedFile {
+    path: PathBuf,
+    label: Option<String>,
+    size: u64,
+    used: SystemTime,
+}
+
+fn cached_files(dir: &Path) -> Result<Vec<CachedFile>> {
+    let mut files = vec![];
+    if !dir.exists() {
+        return Ok(files);
+    }
+    for file in std::fs::read_dir(dir)? {
+        let path = file?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // segments of older versions are leftovers
+        let is_segment = path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION)
+            && !name.contains(".part.");
+        let metadata_path = path.with_extension("json");
+        if name.ends_with(".json") && path.with_extension(SEGMENT_EXTENSION).exists() {
+            continue;
+        }
+        let label = if is_segment && metadata_path.exists() {
+            serde_json::from_str::<CacheEntry>(&std::fs::read_to_string(&metadata_path)?)
+                .ok()
+                .map(|entry| entry.id)
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == LOUDNESS_EXTENSION)
+        {
+            serde_json::from_str::<LoudnessEntry>(&std::fs::read_to_string(&path)?)
+                .ok()
+                .map(|entry| {
+                    let name = entry.source.file_name().unwrap_or_default();
+                    format!("loudness of {}", name.to_string_lossy())
+                })
+        } else {
+            None
+        };
+        let metadata = std::fs::metadata(&path)?;
+        files.push(CachedFile {
+            path,
+            label,
+            size: metadata.len(),
+            used: metadata.modified()?,
+        });
+    }
+    files.sort_by_key(|file| std::cmp::Reverse(file.used));
+    Ok(files)
+}
+
+fn megabytes(size: u64) -> String {
+    format!("{:.1} MB", size as f64 / 1_000_000.0)
+}
+
+fn list_cache(args: &CacheDirArgs) -> Result<()> {
+    let files = cached_files(&args.cache_dir)?;
+    let mut table = Table::new();
+    table.set_header(vec!["key", "entry", "size", "last used"]);
+    for file in files.iter() {
+        let key = file
+            .path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .chars()
+            .take(12)
+            .collect::<String>();
+        let label = file.label.clone().unwrap_or("(leftover)".to_string());
+        let used: DateTime<Local> = file.used.into();
+        table.add_row(vec![
+            key,
+            label,
+            megabytes(file.size),
+            used.format("%Y-%m-%d %H:%M").to_string(),
+        ]);
+    }
+    println!("{}", table);
+    println!(
+        "{} files, {} in {}",
+        files.len(),
+        megabytes(files.iter().map(|file| file.size).sum()),
+        args.cache_dir.display()
+    );
+    Ok(())
+}
+
+fn gc_cache(args: &GcArgs) -> Result<()> {
+    let max_age = Duration::from_secs(args.days * 24 * 60 * 60);
+    let now = SystemTime::now();
+    let (mut count, mut size) = (0, 0);
+    for file in cached_files(&args.dir.cache_dir)? {
+        let age = now.duration_since(file.used).unwrap_or_default();
+        if !args.all && file.label.is_some() && age < max_age {
+            continue;
+        }
+        std::fs::remove_file(&file.path)?;
+        let metadata_path = file.path.with_extension("json");
+        if metadata_path != file.path && metadata_path.exists() {
+            std::fs::remove_file(metadata_path)?;
+        }
+        count += 1;
+        size += file.size;
+    }
+    log::debug!("{} files removed, {} freed", count, megabytes(size));
+    Ok(())
+}
+
+pub fn cache_command(args: &CacheArgs) -> Result<()> {
+    match &args.command {
+        CacheCommand::Ls(args) => list_cache(args),
+        CacheCommand::Gc(args) => gc_cache(args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_key;
+
+    #[test]
+    fn test_hash_key() {
+        let stamps = vec!["a.mp3 100 1".to_string()];
+        let key = hash_key("ffmpeg version 6.1", &stamps, "slice (1.0, 2.0)");
+        assert_eq!(key.len(), 64);
+        assert_eq!(
+            key,
+            hash_key("ffmpeg version 6.1", &stamps, "slice (1.0, 2.0)")
+        );
+        assert_ne!(
+            key,
+            hash_key("ffmpeg version 6.1", &stamps, "slice (1.0, 2.5)")
+        );
+        assert_ne!(
+            key,
+            hash_key("ffmpeg version 7.0", &stamps, "slice (1.0, 2.0)")
+        );
+        let touched = vec!["a.mp3 100 2".to_string()];
+        assert_ne!(
+            key,
+            hash_key("ffmpeg version 6.1", &touched, "slice (1.0, 2.0)")
+        );
+    }
+}
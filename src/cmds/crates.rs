@@ -0,0 +1,43 @@
+use super::playlist::{fetch_track, print_tracks, write_mix_tracks, PlaylistModel};
+use crate::mixxx::{crate_track::CrateTrack, crates::Crate, key::KeyNotation, repo::AsRepo};
+use anyhow::Result;
+use rusqlite::Connection;
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Parser)]
+pub struct CrateArgs {
+    #[arg(long)]
+    crate_id: i32,
+    #[arg(long)]
+    out: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = KeyNotation::Lancelot)]
+    key_notation: KeyNotation,
+}
+
+pub fn fetch_crate(conn: &Connection, id: i32) -> Result<PlaylistModel> {
+    let crate_repo = Crate::repo(conn);
+    let crate_track_repo = CrateTrack::repo(conn);
+
+    let c = crate_repo
+        .select(id)?
+        .ok_or(anyhow::anyhow!("crate id={} not found", id))?;
+    let tracks = crate_track_repo
+        .find_by_crate_id(id)?
+        .iter()
+        .enumerate()
+        .map(|(i, crate_track)| fetch_track(conn, crate_track.track_id, i as i32 + 1))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(PlaylistModel {
+        title: c.name,
+        tracks,
+    })
+}
+
+pub fn list_crate_tracks(conn: &Connection, args: &CrateArgs) -> Result<()> {
+    let c = fetch_crate(conn, args.crate_id)?;
+    print_tracks(&c, args.key_notation);
+    if let Some(out) = &args.out {
+        write_mix_tracks(c, out)?;
+    }
+    Ok(())
+}
@@ -1,7 +1,18 @@
+use super::{
+    mix_plan::{EqEnvelope, FadeCurve, ThreeBandEq},
+    transition::{Transition, TransitionKind},
+};
 use crate::{
-    cmds::utils::{cue_at, get_hotcue, get_track},
-    ffmpeg::ffmpeg_complex_filter,
-    mixxx::{cue::Cue, library::Library},
+    cmds::utils::{check_downbeat, cue_downbeat, get_hotcue, get_track},
+    ffmpeg::{
+        encoder::Encoding,
+        ffmpeg_complex_filter,
+        filter_graph::{Filter, FilterGraph, Pad},
+        progress::{seconds_bar, update_seconds},
+        stepped_tempo_filter::{check_tempo_modes, TempoMode},
+        Input,
+    },
+    mixxx::{beats::BeatGrid, cue::Cue, library::Library},
 };
 use anyhow::Result;
 use rusqlite::Connection;
@@ -21,6 +32,13 @@ pub struct CrossFadeArgs {
     pub crossfade: u32,
     #[arg(long)]
     pub bpm: f32,
+    /// milliseconds to move b later by, after aligning downbeats
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    pub nudge_ms: f32,
+    #[arg(long, value_enum, default_value_t)]
+    pub a_tempo_mode: TempoMode,
+    #[arg(long, value_enum, default_value_t)]
+    pub b_tempo_mode: TempoMode,
     #[arg(long)]
     pub out: PathBuf,
 }
@@ -29,12 +47,31 @@ pub struct CrossFadeArgs {
 pub struct CrossFadeCommand {
     pub a_path: PathBuf,
     pub a: Library,
+    pub a_grid: BeatGrid,
     pub a_cue: Cue,
     pub b_path: PathBuf,
     pub b: Library,
+    pub b_grid: BeatGrid,
     pub b_cue: Cue,
     pub crossfade: u32,
     pub bpm: f32,
+    /// beats after `a_cue` to start at
+    pub a_offset: i32,
+    pub kind: TransitionKind,
+    pub curve: FadeCurve,
+    pub a_eq: ThreeBandEq,
+    pub b_eq: ThreeBandEq,
+    pub a_envelope: EqEnvelope,
+    pub b_envelope: EqEnvelope,
+    /// milliseconds to move b later by, after aligning downbeats
+    pub nudge_ms: f32,
+    pub a_tempo_mode: TempoMode,
+    pub b_tempo_mode: TempoMode,
+    /// dB
+    pub a_gain: f32,
+    pub b_gain: f32,
+    /// single-pass loudnorm of both tracks, off when the gains normalize them
+    pub loudnorm: bool,
 }
 
 impl CrossFadeCommand {
@@ -48,19 +85,36 @@ impl CrossFadeCommand {
         bpm: f32,
     ) -> Result<Self> {
         let (a_path, a) = get_track(conn, a_id)?;
+        let a_grid = BeatGrid::from_library(&a)?;
         let a_cue = get_hotcue(conn, a_id, a_hotcue)?;
 
         let (b_path, b) = get_track(conn, b_id)?;
+        let b_grid = BeatGrid::from_library(&b)?;
         let b_cue = get_hotcue(conn, b_id, b_hotcue)?;
         Ok(Self {
             a_path,
             a,
+            a_grid,
             a_cue,
             b_path,
             b,
+            b_grid,
             b_cue,
             crossfade,
             bpm,
+            a_offset: 0,
+            kind: TransitionKind::default(),
+            curve: FadeCurve::default(),
+            a_eq: ThreeBandEq::bass_cut(),
+            b_eq: ThreeBandEq::default(),
+            a_envelope: EqEnvelope::default(),
+            b_envelope: EqEnvelope::default(),
+            nudge_ms: 0.0,
+            a_tempo_mode: TempoMode::default(),
+            b_tempo_mode: TempoMode::default(),
+            a_gain: 0.0,
+            b_gain: 0.0,
+            loudnorm: true,
         })
     }
 
@@ -71,49 +125,186 @@ impl CrossFadeCommand {
         )
     }
 
-    pub fn complex_filter(&self) -> Vec<String> {
-        let curve = "squ";
-        let bpm = self.bpm;
-        let a_scale = bpm / self.a.bpm;
-        let b_scale = bpm / self.b.bpm;
+    /// seconds of each source track to start at, on the downbeats of the cues
+    fn starts(&self) -> (f32, f32) {
+        let a_downbeat = cue_downbeat(&self.a, &self.a_grid, &self.a_cue);
+        (
+            self.a_grid.shift(a_downbeat, self.a_offset as f32),
+            cue_downbeat(&self.b, &self.b_grid, &self.b_cue),
+        )
+    }
 
-        let a_beat = 60.0 / self.a.bpm;
-        let a_cross = a_beat * self.crossfade as f32;
-        let b_beat = 60.0 / self.b.bpm;
-        let b_cross = b_beat * self.crossfade as f32;
+    /// seconds of each source track to cross
+    fn crosses(&self) -> (f32, f32) {
+        let (a_cue_at, b_cue_at) = self.starts();
+        (
+            self.a_grid.shift(a_cue_at, self.crossfade as f32) - a_cue_at,
+            self.b_grid.shift(b_cue_at, self.crossfade as f32) - b_cue_at,
+        )
+    }
 
-        let a_cue_at = cue_at(&self.a, &self.a_cue);
-        let b_cue_at = cue_at(&self.b, &self.b_cue);
+    /// tempo of each deck as rendered
+    fn scales(&self) -> (f32, f32) {
+        (
+            self.a_tempo_mode.scale(self.bpm / self.a.bpm),
+            self.b_tempo_mode.scale(self.bpm / self.b.bpm),
+        )
+    }
 
-        let a_filters = vec![
-            format!("[0] atrim=start={}:duration={} [0_1]", a_cue_at, a_cross),
-            format!(
-                "[0_1] afade=t=out:st={}:duration={}:curve={} [0_2]",
-                0.0, a_cross, curve,
-            ),
-            format!("[0_2] loudnorm [0_3]"),
-            format!("[0_3] equalizer=f=300:t=h:width=200:g=-10 [0_4]"),
-            format!("[0_4] atempo={} [0_out]", a_scale),
-        ];
-        let b_filters = vec![
-            format!(
-                "[1] atrim=start={}:duration={},asetpts=PTS-STARTPTS [1_1]",
-                b_cue_at, b_cross,
-            ),
-            format!(
-                "[1_1] afade=t=in:st={}:duration={}:curve={} [1_2]",
-                0.0, b_cross, curve,
+    /// logs the phase error and the semitones vinyl mode moves the keys by
+    pub fn log_alignment(&self) {
+        log::info!("{}: phase error {:.1} ms", self.id(), self.phase_error());
+        let (a_scale, b_scale) = self.scales();
+        for (track, mode, scale) in [
+            (&self.a, self.a_tempo_mode, a_scale),
+            (&self.b, self.b_tempo_mode, b_scale),
+        ] {
+            if mode == TempoMode::Vinyl {
+                log::info!(
+                    "{}: key of track id={} shifted {:+.2} semitones",
+                    self.id(),
+                    track.id,
+                    mode.key_shift(scale)
+                );
+            }
+        }
+    }
+
+    /// seconds of the rendered crossfade, b's part including the nudge so that
+    /// the next slice continues it
+    pub fn duration(&self) -> f32 {
+        let (_, b_cross) = self.crosses();
+        b_cross / self.scales().1 + self.nudge_ms / 1000.0
+    }
+
+    /// milliseconds b's beats are late on a's beats at most over the crossfade,
+    /// grids with varying tempos drift apart
+    pub fn phase_error(&self) -> f32 {
+        let (a_start, b_start) = self.starts();
+        let (a_scale, b_scale) = self.scales();
+        (0..=self.crossfade)
+            .map(|beat| {
+                let a_at = (self.a_grid.shift(a_start, beat as f32) - a_start) / a_scale;
+                let b_at = (self.b_grid.shift(b_start, beat as f32) - b_start) / b_scale;
+                (b_at - a_at) * 1000.0 + self.nudge_ms
+            })
+            .fold(
+                0.0,
+                |max: f32, error| {
+                    if error.abs() > max.abs() {
+                        error
+                    } else {
+                        max
+                    }
+                },
+            )
+    }
+
+    /// filters of one deck from `start` for `cross` seconds of the source, at the mix tempo
+    fn deck_filters(
+        &self,
+        start: f32,
+        cross: f32,
+        gain: f32,
+        mode: TempoMode,
+        scale: f32,
+    ) -> Vec<Filter> {
+        [
+            vec![
+                Filter::new("atrim")
+                    .arg("start", start)
+                    .arg("duration", cross),
+                Filter::new("asetpts").arg("expr", "PTS-STARTPTS"),
+            ],
+            self.loudnorm
+                .then(|| Filter::new("loudnorm"))
+                .into_iter()
+                .collect(),
+            vec![Filter::new("volume").arg("volume", format!("{}dB", gain))],
+            mode.filters(scale),
+        ]
+        .concat()
+    }
+
+    pub fn transition(&self) -> Transition {
+        Transition {
+            kind: self.kind,
+            curve: self.curve,
+            out_eq: self.a_eq,
+            in_eq: self.b_eq,
+            out_envelope: self.a_envelope.clone(),
+            in_envelope: self.b_envelope.clone(),
+            duration: self.duration(),
+            beat: 60.0 / self.bpm,
+        }
+    }
+
+    pub fn inputs(&self) -> Vec<Input> {
+        vec![Input::new(&self.a_path), Input::new(&self.b_path)]
+    }
+
+    /// adds filters mixing the whole inputs `a` and `b` to `graph`
+    pub fn add_filters(&self, graph: &mut FilterGraph, a: Pad, b: Pad) -> Pad {
+        let (a_scale, b_scale) = self.scales();
+        let (a_cue_at, b_cue_at) = self.starts();
+        let (a_cross, b_cross) = self.crosses();
+        // b starts earlier in its source to play its downbeat later
+        let nudge = self.nudge_ms / 1000.0 * b_scale;
+        // a ends with b when nudged earlier
+        let a_cross = a_cross.min(self.duration() * a_scale);
+
+        let a_deck = graph.chain(
+            vec![a],
+            self.deck_filters(a_cue_at, a_cross, self.a_gain, self.a_tempo_mode, a_scale),
+        );
+        let b_deck = graph.chain(
+            vec![b],
+            self.deck_filters(
+                b_cue_at - nudge,
+                b_cross + nudge,
+                self.b_gain,
+                self.b_tempo_mode,
+                b_scale,
             ),
-            format!("[1_2] loudnorm [1_3]"),
-            // format!("[1_3] equalizer=f=300:t=h:width=200:g=-10 [1_4]"),
-            format!("[1_3] atempo={} [1_out]", b_scale),
-        ];
-        println!(
-            "A  : @{} {}s + {}s\nB  : @{} {}s + {}s",
-            self.a_cue.hotcue, a_cue_at, a_cross, self.b_cue.hotcue, b_cue_at, b_cross,
         );
-        println!(
-            "a_cross {} / {} = {}, b_cross {} / {} = {}",
+        self.transition().add_filters(graph, a_deck, b_deck)
+    }
+
+    pub fn filter_graph(&self) -> (FilterGraph, Pad) {
+        let mut graph = FilterGraph::new();
+        let out = self.add_filters(&mut graph, Pad::input(0), Pad::input(1));
+        (graph, out)
+    }
+
+    /// everything the rendered audio depends on besides the source files
+    pub fn definition(&self) -> String {
+        let (graph, graph_out) = self.filter_graph();
+        format!("crossfade {} {}", graph, graph_out)
+    }
+
+    /// calls `progress` with seconds rendered
+    pub fn render(
+        &self,
+        out: &Path,
+        output_args: Vec<String>,
+        progress: impl FnMut(f32) -> Result<()>,
+    ) -> Result<()> {
+        let (a_cue_at, b_cue_at) = self.starts();
+        let (a_cross, b_cross) = self.crosses();
+        let (a_scale, b_scale) = self.scales();
+        log::debug!(
+            "{}: A @{} {}s + {}s, B @{} {}s + {}s",
+            self.id(),
+            self.a_cue.hotcue,
+            a_cue_at,
+            a_cross,
+            self.b_cue.hotcue,
+            b_cue_at,
+            b_cross,
+        );
+        log::debug!(
+            "{}: a_cross {} / {} = {}, b_cross {} / {} = {}",
+            self.id(),
             a_cross,
             a_scale,
             a_cross / a_scale,
@@ -121,16 +312,26 @@ impl CrossFadeCommand {
             b_scale,
             b_cross / b_scale,
         );
-        vec![
-            a_filters,
-            b_filters,
-            vec![format!("[0_out][1_out] amix=duration=longest [out]")],
-        ]
-        .concat()
+        let (graph, graph_out) = self.filter_graph();
+        ffmpeg_complex_filter(
+            self.inputs(),
+            out,
+            &graph,
+            &graph_out,
+            output_args,
+            progress,
+        )
     }
 
     pub fn execute(&self, out: &Path) -> Result<()> {
-        let filters = self.complex_filter();
-        ffmpeg_complex_filter(vec![&self.a_path, &self.b_path], out, filters)
+        check_tempo_modes([self.a_tempo_mode, self.b_tempo_mode].into_iter())?;
+        check_downbeat(&self.a, &self.a_grid, &self.a_cue);
+        check_downbeat(&self.b, &self.b_grid, &self.b_cue);
+        self.log_alignment();
+        let mut bar = seconds_bar(self.id(), self.duration(), 0);
+        self.render(out, Encoding::default().args(out)?, |seconds| {
+            update_seconds(&mut bar, seconds);
+            Ok(())
+        })
     }
 }
@@ -1,36 +1,114 @@
-use super::{cross_fade::CrossFadeCommand, slice::SliceCommand};
-use crate::ffmpeg::concat_cmd;
+use super::utils::check_track;
+use super::{
+    cache::{CacheDirArgs, RenderCache},
+    cross_fade::CrossFadeCommand,
+    mix_plan::MixPlan,
+    slice::SliceCommand,
+    tracklist::{Tracklist, TracklistEntry},
+    work_dir::WorkDir,
+};
+use crate::{
+    ffmpeg::{
+        concat_cmd,
+        encoder::{segment_args, Encoding, OutputFormat, MIX_SAMPLE_RATE},
+        ffmpeg_complex_filter,
+        filter_graph::{Filter, FilterGraph, Pad},
+        loudness::{measure_loudness, Loudness},
+        probe::ffprobe,
+        progress::{seconds_bar, update_seconds},
+        stepped_tempo_filter::{check_tempo_modes, TempoMode},
+        Input,
+    },
+    mixxx::{key::Key, library::Library},
+};
 use anyhow::Result;
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
-use std::{fs::OpenOptions, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::OpenOptions,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
 use std::{io::Write, path::Path};
 
+/// dB the true peak limit may keep a track below the target loudness without a warning
+const PEAK_CUT_WARNING: f32 = 1.0;
+
 #[derive(Debug, clap::Parser)]
 pub struct CreateMixArgs {
+    /// mix plan (.toml, .json) or legacy mix csv
     pub input: PathBuf,
     #[arg(long)]
     pub out: PathBuf,
+    /// segments rendered at once
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
+    #[command(flatten)]
+    pub cache: CacheDirArgs,
+    /// render the whole mix with one ffmpeg filter graph, without intermediates
+    #[arg(long, conflicts_with_all = ["jobs", "work_dir", "keep_intermediates"])]
+    pub single_pass: bool,
+    /// directory of intermediate files, a new temporary one by default
+    #[arg(long)]
+    pub work_dir: Option<PathBuf>,
+    /// keep the work dir, with the segments rendered there by `--no-cache`
+    #[arg(long)]
+    pub keep_intermediates: bool,
+    /// render segments in the work dir instead of the cache dir
+    #[arg(long)]
+    pub no_cache: bool,
+    #[command(flatten)]
+    pub encoding: Encoding,
+    #[arg(long, value_enum, default_value_t = Normalize::Segment)]
+    pub normalize: Normalize,
+    /// integrated loudness of each track with two-pass normalization, LUFS
+    #[arg(long, default_value_t = -14.0, allow_hyphen_values = true)]
+    pub target_lufs: f32,
+    /// maximum true peak of each track with two-pass normalization, dBTP
+    #[arg(long, default_value_t = -1.0, allow_hyphen_values = true)]
+    pub true_peak: f32,
+    /// take the loudness of tracks analyzed by mixxx from their replaygain instead of measuring,
+    /// tracks it would turn up are still measured for their true peak
+    #[arg(long)]
+    pub replaygain: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Normalize {
+    /// one linear gain per track from an EBU R128 measurement of the whole track
+    TwoPass,
+    /// single-pass loudnorm of each segment
+    Segment,
+    Off,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MixTrack {
-    position: usize,
-    id: i32,
-    title: String,
-    begin_hotcue: u8,
-    begin_offset: i32,
-    end_hotcue: u8,
-    bpm: Option<f32>,
+    pub position: usize,
+    pub id: i32,
+    pub title: String,
+    /// Lancelot notation, informational only
+    #[serde(default)]
+    pub key: Option<Key>,
+    pub begin_hotcue: u8,
+    pub begin_offset: i32,
+    pub end_hotcue: u8,
+    pub bpm: Option<f32>,
     pub to_bpm: Option<f32>,
-    crossfade: usize,
+    pub crossfade: usize,
 }
 
 impl MixTrack {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         position: usize,
         id: i32,
         title: String,
+        key: Option<Key>,
         begin_hotcue: u8,
         begin_offset: i32,
         end_hotcue: u8,
@@ -42,6 +120,7 @@ impl MixTrack {
             position,
             id,
             title,
+            key,
             begin_hotcue,
             begin_offset,
             end_hotcue,
@@ -58,95 +137,453 @@ pub enum MixOp {
     CrossFade(Box<CrossFadeCommand>),
 }
 
+impl MixOp {
+    pub fn id(&self) -> String {
+        match self {
+            MixOp::Slice(slice) => slice.id(),
+            MixOp::CrossFade(cross_fade) => cross_fade.id(),
+        }
+    }
+
+    /// seconds of the rendered op
+    pub fn duration(&self) -> f32 {
+        match self {
+            MixOp::Slice(slice) => slice.duration(),
+            MixOp::CrossFade(cross_fade) => cross_fade.duration(),
+        }
+    }
+
+    pub fn sources(&self) -> Vec<PathBuf> {
+        match self {
+            MixOp::Slice(slice) => vec![slice.path().to_path_buf()],
+            MixOp::CrossFade(cross_fade) => {
+                vec![cross_fade.a_path.clone(), cross_fade.b_path.clone()]
+            }
+        }
+    }
+
+    pub fn definition(&self) -> String {
+        match self {
+            MixOp::Slice(slice) => slice.definition(),
+            MixOp::CrossFade(cross_fade) => cross_fade.definition(),
+        }
+    }
+
+    pub fn inputs(&self) -> Vec<Input> {
+        match self {
+            MixOp::Slice(slice) => vec![slice.input()],
+            MixOp::CrossFade(cross_fade) => cross_fade.inputs(),
+        }
+    }
+
+    /// adds filters reading a pad of each of `inputs` to `graph`
+    pub fn add_filters(&self, graph: &mut FilterGraph, mut inputs: Vec<Pad>) -> Pad {
+        match self {
+            MixOp::Slice(slice) => slice.add_filters(graph, inputs.remove(0)),
+            MixOp::CrossFade(cross_fade) => {
+                let b = inputs.remove(1);
+                cross_fade.add_filters(graph, inputs.remove(0), b)
+            }
+        }
+    }
+
+    pub fn tempo_modes(&self) -> Vec<TempoMode> {
+        match self {
+            MixOp::Slice(slice) => vec![slice.tempo_mode],
+            MixOp::CrossFade(cross_fade) => vec![cross_fade.a_tempo_mode, cross_fade.b_tempo_mode],
+        }
+    }
+
+    /// renders a lossless segment, calls `progress` with seconds rendered,
+    /// an error stops rendering
+    pub fn render(&self, out: &Path, progress: impl FnMut(f32) -> Result<()>) -> Result<()> {
+        match self {
+            MixOp::Slice(slice) => slice.render(out, segment_args(), progress),
+            MixOp::CrossFade(cross_fade) => cross_fade.render(out, segment_args(), progress),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MixList {
     pub ops: Vec<MixOp>,
 }
 
 impl MixList {
-    pub fn from_tracks(conn: &Connection, tracks: &[MixTrack]) -> Result<Self> {
-        assert!(tracks.len() > 1);
-        assert!(tracks[0].bpm.is_some());
-
+    pub fn from_plan(conn: &Connection, plan: &MixPlan) -> Result<Self> {
         let mut ops = vec![];
-        let mut current_bpm = tracks[0].bpm.unwrap();
-        for i in 0..tracks.len() - 1 {
-            let (a, b) = (&tracks[i], &tracks[i + 1]);
-            let begin_offset = if i == 0 {
-                0
-            } else {
-                tracks[i - 1].crossfade as i32
+        let mut current_bpm = plan.tracks[0]
+            .bpm
+            .ok_or(anyhow::anyhow!("the first track needs bpm"))?;
+        for (i, track) in plan.tracks.iter().enumerate() {
+            if let Some(bpm) = track.bpm {
+                current_bpm = bpm;
+            }
+            let incoming = i
+                .checked_sub(1)
+                .and_then(|prev| plan.tracks[prev].transition.as_ref());
+            let begin_offset = match incoming {
+                Some(transition) => transition.beats as i32,
+                None => track.begin_offset,
             };
-            let a_slice = SliceCommand::new(
+            let mut slice = SliceCommand::new(
                 conn,
-                a.id,
-                a.begin_hotcue,
+                track.id,
+                track.begin_hotcue,
                 begin_offset,
-                a.end_hotcue,
-                0,
+                track.end_hotcue,
+                track.end_offset,
                 current_bpm,
-                a.to_bpm,
+                track.to_bpm,
             )?;
-            if let Some(to_bpm) = a.to_bpm {
+            slice.gain = track.gain;
+            slice.loop_extension = track.loop_extension;
+            slice.ramp_beats = track.ramp_beats;
+            slice.tempo_mode = track.tempo_mode;
+            if let Some(to_bpm) = track.to_bpm {
                 current_bpm = to_bpm;
             }
-            ops.push(MixOp::Slice(Box::new(a_slice)));
+            ops.push(MixOp::Slice(Box::new(slice)));
 
-            if a.crossfade == 0 {
+            let (Some(transition), Some(next)) = (&track.transition, plan.tracks.get(i + 1)) else {
                 continue;
-            }
-            let cross_fade = CrossFadeCommand::new(
+            };
+            let mut cross_fade = CrossFadeCommand::new(
                 conn,
-                a.id,
-                a.end_hotcue,
-                b.id,
-                b.begin_hotcue,
-                a.crossfade as u32,
+                track.id,
+                track.end_hotcue,
+                next.id,
+                next.begin_hotcue,
+                transition.beats,
                 current_bpm,
             )?;
+            cross_fade.a_offset = track.end_offset;
+            cross_fade.kind = transition.kind;
+            cross_fade.curve = transition.curve;
+            cross_fade.a_eq = transition.out_eq;
+            cross_fade.b_eq = transition.in_eq;
+            cross_fade.a_envelope = transition.out_envelope.clone();
+            cross_fade.b_envelope = transition.in_envelope.clone();
+            cross_fade.nudge_ms = transition.nudge_ms;
+            cross_fade.a_tempo_mode = track.tempo_mode;
+            cross_fade.b_tempo_mode = next.tempo_mode;
+            cross_fade.a_gain = track.gain;
+            cross_fade.b_gain = next.gain;
             ops.push(MixOp::CrossFade(Box::new(cross_fade)));
-            if i == tracks.len() - 2 {
-                let b_slice = SliceCommand::new(
-                    conn,
-                    b.id,
-                    b.begin_hotcue,
-                    0,
-                    b.end_hotcue,
-                    0,
-                    current_bpm,
-                    None,
-                )?;
-                ops.push(MixOp::Slice(Box::new(b_slice)));
-            }
         }
         Ok(Self { ops })
     }
 
-    pub fn execute(&self, out: &Path) -> Result<()> {
-        let mut file_paths = vec![];
+    /// start of each track in the rendered mix, a crossfade starts the next track
+    pub fn tracklist(&self) -> Tracklist {
+        let mut entries: Vec<TracklistEntry> = vec![];
+        let mut seconds = 0.0;
         for op in self.ops.iter() {
-            let out = match op {
-                MixOp::Slice(slice) => PathBuf::from(format!("{}.mp3", slice.id())),
-                MixOp::CrossFade(cross_fade) => PathBuf::from(format!("{}.mp3", cross_fade.id())),
+            let track: &Library = match op {
+                MixOp::Slice(slice) => slice.track(),
+                MixOp::CrossFade(cross_fade) => &cross_fade.b,
             };
-            file_paths.push(format!("file \'{}\'", out.display()));
-            if out.exists() {
-                continue;
+            if entries.last().map(|entry| entry.track_id) != Some(track.id) {
+                entries.push(TracklistEntry {
+                    start: seconds,
+                    track_id: track.id,
+                    title: track.title.clone(),
+                    artist: track.artist.clone(),
+                });
+            }
+            seconds += op.duration();
+        }
+        Tracklist {
+            entries,
+            duration: seconds,
+        }
+    }
+
+    /// source file and library row of each track id
+    fn tracks(&self) -> BTreeMap<i32, (&Path, &Library)> {
+        let mut tracks = BTreeMap::new();
+        for op in self.ops.iter() {
+            match op {
+                MixOp::Slice(slice) => {
+                    tracks.insert(slice.track().id, (slice.path(), slice.track()));
+                }
+                MixOp::CrossFade(cross_fade) => {
+                    tracks.insert(
+                        cross_fade.a.id,
+                        (cross_fade.a_path.as_path(), &cross_fade.a),
+                    );
+                    tracks.insert(
+                        cross_fade.b.id,
+                        (cross_fade.b_path.as_path(), &cross_fade.b),
+                    );
+                }
+            }
+        }
+        tracks
+    }
+
+    /// compares each source file with its library row
+    pub fn check_tracks(&self) -> Result<()> {
+        for (path, library) in self.tracks().values() {
+            let info = ffprobe(path)?;
+            log::debug!("{}: {:?}", path.display(), info);
+            for problem in check_track(library, &info) {
+                log::warn!("track id={} {}: {}", library.id, library.title, problem);
+            }
+        }
+        Ok(())
+    }
+
+    /// renders ops missing in `cache` on `jobs` threads, the first error stops the others.
+    /// returns the cached file of each op
+    fn render_ops(&self, cache: &RenderCache, jobs: u16, duration: f32) -> Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        let mut done = 0.0;
+        let mut pending = vec![];
+        let mut pending_keys = HashSet::new();
+        for op in self.ops.iter() {
+            let entry = cache.entry(op.id(), op.sources(), op.definition());
+            let key = entry.key()?;
+            let path = cache.path(&key);
+            if path.exists() {
+                cache.touch(&key)?;
+                done += op.duration();
+            } else if pending_keys.insert(key.clone()) {
+                pending.push((op, key, entry));
+            } else {
+                // rendered once for every op with the same definition
+                done += op.duration();
+            }
+            paths.push(path);
+        }
+        // the overall bar and seconds rendered of each pending op
+        let total = Mutex::new((seconds_bar("mix", duration, 0), vec![0.0; pending.len()]));
+        let update_total = |i: usize, seconds: f32| {
+            let mut total = total.lock().unwrap();
+            total.1[i] = seconds;
+            let seconds = done + total.1.iter().sum::<f32>();
+            update_seconds(&mut total.0, seconds);
+        };
+        update_seconds(&mut total.lock().unwrap().0, done);
+
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let first_error = Mutex::new(None);
+        std::thread::scope(|scope| {
+            // bars of workers below the overall one
+            for position in (1..=jobs).take(pending.len()) {
+                let (pending, next, failed, first_error, update_total) =
+                    (&pending, &next, &failed, &first_error, &update_total);
+                scope.spawn(move || loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let Some((op, key, entry)) = pending.get(i) else {
+                        break;
+                    };
+                    if failed.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let mut bar = seconds_bar(op.id(), op.duration(), position);
+                    let result = cache.insert(key, entry, |out| {
+                        op.render(out, |seconds| {
+                            if failed.load(Ordering::SeqCst) {
+                                anyhow::bail!("cancelled");
+                            }
+                            update_seconds(&mut bar, seconds);
+                            update_total(i, seconds);
+                            Ok(())
+                        })
+                    });
+                    match result {
+                        Ok(()) => update_total(i, op.duration()),
+                        Err(e) => {
+                            if !failed.swap(true, Ordering::SeqCst) {
+                                *first_error.lock().unwrap() = Some(e);
+                            }
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        match first_error.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => Ok(paths),
+        }
+    }
+
+    /// replaces loudnorm of each segment with one gain per track, added to the planned gain
+    fn normalize(&mut self, args: &CreateMixArgs, cache: &RenderCache) -> Result<()> {
+        let mut gains = BTreeMap::new();
+        if args.normalize == Normalize::TwoPass {
+            for (id, (path, library)) in self.tracks() {
+                let replaygain = Loudness::from_replaygain(library.replaygain);
+                let loudness = match replaygain.filter(|_| args.replaygain) {
+                    // without a true peak only turning down is safe
+                    Some(loudness) if loudness.gain(args.target_lufs, args.true_peak) <= 0.0 => {
+                        loudness
+                    }
+                    _ => cache.loudness(path)?,
+                };
+                let gain = loudness.gain(args.target_lufs, args.true_peak);
+                log::info!(
+                    "track id={} {}: {:.1} LUFS, gain {:+.1} dB",
+                    id,
+                    library.title,
+                    loudness.integrated,
+                    gain
+                );
+                let cut = loudness.peak_cut(args.target_lufs, args.true_peak);
+                if cut > PEAK_CUT_WARNING {
+                    log::warn!(
+                        "track id={} {}: the true peak limit keeps it {:.1} dB below {} LUFS",
+                        id,
+                        library.title,
+                        cut,
+                        args.target_lufs
+                    );
+                }
+                gains.insert(id, gain);
             }
+        }
+        let gain = |id: i32| gains.get(&id).copied().unwrap_or(0.0);
+        for op in self.ops.iter_mut() {
             match op {
-                MixOp::Slice(slice) => slice.execute(&out)?,
-                MixOp::CrossFade(cross_fade) => cross_fade.execute(&out)?,
-            };
+                MixOp::Slice(slice) => {
+                    slice.loudnorm = false;
+                    slice.gain += gain(slice.track().id);
+                }
+                MixOp::CrossFade(cross_fade) => {
+                    cross_fade.loudnorm = false;
+                    cross_fade.a_gain += gain(cross_fade.a.id);
+                    cross_fade.b_gain += gain(cross_fade.b.id);
+                }
+            }
         }
-        let file_list_path = PathBuf::from("./filelist.txt");
+        Ok(())
+    }
+
+    /// every op in one filter graph, concatenated in order
+    pub fn filter_graph(&self) -> (Vec<Input>, FilterGraph, Pad) {
+        let mut inputs = vec![];
+        let mut graph = FilterGraph::new();
+        let mut outs = vec![];
+        for op in self.ops.iter() {
+            let op_inputs = op.inputs();
+            let pads = (inputs.len()..inputs.len() + op_inputs.len())
+                .map(Pad::input)
+                .collect();
+            inputs.extend(op_inputs);
+            let out = op.add_filters(&mut graph, pads);
+            // concat takes one format
+            outs.push(graph.chain(
+                vec![out],
+                vec![Filter::new("aformat")
+                    .arg("sample_fmts", "flt")
+                    .arg("sample_rates", MIX_SAMPLE_RATE)
+                    .arg("channel_layouts", "stereo")],
+            ));
+        }
+        let n = outs.len();
+        let out = graph.chain(
+            outs,
+            vec![Filter::new("concat").arg("n", n).arg("v", 0).arg("a", 1)],
+        );
+        (inputs, graph, out)
+    }
+
+    fn render_single_pass(&self, out: &Path, encoding: &Encoding, duration: f32) -> Result<()> {
+        let (inputs, graph, graph_out) = self.filter_graph();
+        let mut bar = seconds_bar("mix", duration, 0);
+        ffmpeg_complex_filter(
+            inputs,
+            out,
+            &graph,
+            &graph_out,
+            encoding.args(out)?,
+            |seconds| {
+                update_seconds(&mut bar, seconds);
+                Ok(())
+            },
+        )
+    }
+
+    /// renders segments into the cache and concatenates them
+    fn render_segments(
+        &self,
+        args: &CreateMixArgs,
+        cache: &RenderCache,
+        duration: f32,
+    ) -> Result<()> {
+        let out = args.out.as_path();
+        let mut work_dir = WorkDir::new(args.work_dir.as_deref(), args.keep_intermediates)?;
+        let segments = if args.no_cache {
+            Some(RenderCache::open(&work_dir.dir("segments")?)?)
+        } else {
+            None
+        };
+        let paths = self.render_ops(segments.as_ref().unwrap_or(cache), args.jobs, duration)?;
+        let file_paths = paths
+            .iter()
+            .map(|path| format!("file \'{}\'", path.display()))
+            .collect::<Vec<_>>();
+        let file_list_path = work_dir.file("filelist.txt");
         let mut f = OpenOptions::new()
             .create(true)
             .truncate(true)
             .write(true)
             .open(&file_list_path)?;
         write!(f, "{}", file_paths.join("\n"))?;
-        if !out.exists() {
-            concat_cmd(&file_list_path, out)?;
+        let mut bar = seconds_bar("concat", duration, 1);
+        concat_cmd(&file_list_path, out, &args.encoding, |seconds| {
+            update_seconds(&mut bar, seconds);
+            Ok(())
+        })
+    }
+
+    pub fn execute(&mut self, args: &CreateMixArgs) -> Result<()> {
+        let out = args.out.as_path();
+        let format = args.encoding.format(out)?;
+        // fails before rendering
+        args.encoding.args(out)?;
+        check_tempo_modes(self.ops.iter().flat_map(|op| op.tempo_modes()))?;
+        self.check_tracks()?;
+        for op in self.ops.iter() {
+            match op {
+                MixOp::Slice(slice) => slice.log_key_shift(),
+                MixOp::CrossFade(cross_fade) => cross_fade.log_alignment(),
+            }
+        }
+        let cache = RenderCache::open(&args.cache.cache_dir)?;
+        if args.normalize != Normalize::Segment {
+            self.normalize(args, &cache)?;
+        }
+        let tracklist = self.tracklist();
+        if args.single_pass {
+            self.render_single_pass(out, &args.encoding, tracklist.duration)?;
+        } else {
+            self.render_segments(args, &cache, tracklist.duration)?;
+        }
+        if args.normalize == Normalize::TwoPass {
+            let loudness = measure_loudness(out)?;
+            log::info!(
+                "{}: {:.1} LUFS, true peak {:.1} dBTP",
+                out.display(),
+                loudness.integrated,
+                loudness.true_peak.unwrap_or_default()
+            );
+        }
+
+        let title = out
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        std::fs::write(
+            out.with_extension("cue"),
+            tracklist.to_cue_sheet(&title, out),
+        )?;
+        std::fs::write(out.with_extension("txt"), tracklist.to_text())?;
+        if format == OutputFormat::Mp3 {
+            tracklist.write_chapters(out)?;
         }
         Ok(())
     }
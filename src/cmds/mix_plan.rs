@@ -0,0 +1,802 @@
+use super::{mix::MixTrack, transition::TransitionKind};
+use crate::{
+    ffmpeg::{
+        filter_graph::{send_commands, Filter, FilterGraph},
+        stepped_tempo_filter::TempoMode,
+    },
+    mixxx::key::Key,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::{collections::HashMap, path::Path};
+use toml_edit::ImDocument;
+
+pub const MIX_PLAN_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanFormat {
+    Toml,
+    Json,
+    /// legacy `MixTrack` rows
+    Csv,
+}
+
+impl PlanFormat {
+    /// by extension, csv unless `.toml` or `.json`
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::Toml,
+            Some("json") => Self::Json,
+            _ => Self::Csv,
+        }
+    }
+}
+
+/// ffmpeg `afade` curves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FadeCurve {
+    Tri,
+    Qsin,
+    Hsin,
+    Esin,
+    Log,
+    Ipar,
+    Qua,
+    Cub,
+    #[default]
+    Squ,
+    Cbr,
+    Par,
+    Exp,
+}
+
+impl FadeCurve {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Tri => "tri",
+            Self::Qsin => "qsin",
+            Self::Hsin => "hsin",
+            Self::Esin => "esin",
+            Self::Log => "log",
+            Self::Ipar => "ipar",
+            Self::Qua => "qua",
+            Self::Cub => "cub",
+            Self::Squ => "squ",
+            Self::Cbr => "cbr",
+            Self::Par => "par",
+            Self::Exp => "exp",
+        }
+    }
+}
+
+/// gains in dB
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThreeBandEq {
+    pub low: f32,
+    pub mid: f32,
+    pub high: f32,
+}
+
+impl ThreeBandEq {
+    /// what the crossfade always applied to the outgoing track
+    pub fn bass_cut() -> Self {
+        Self {
+            low: -10.0,
+            ..Default::default()
+        }
+    }
+
+    /// filters of the bands, without gain
+    fn bands(self) -> [(f32, Filter); 3] {
+        [
+            (
+                self.low,
+                Filter::new("equalizer")
+                    .arg("f", 300)
+                    .arg("t", "h")
+                    .arg("width", 200),
+            ),
+            (
+                self.mid,
+                Filter::new("equalizer")
+                    .arg("f", 1000)
+                    .arg("t", "h")
+                    .arg("width", 1200),
+            ),
+            (self.high, Filter::new("treble").arg("f", 4000)),
+        ]
+    }
+
+    /// one filter per band with a gain, bands with keyframes in `envelope` follow
+    /// them instead, `beat` in seconds
+    pub fn automate(
+        self,
+        envelope: &EqEnvelope,
+        beat: f32,
+        graph: &mut FilterGraph,
+    ) -> Vec<Filter> {
+        let mut filters = vec![];
+        for ((gain, band), keyframes) in self.bands().into_iter().zip(envelope.bands()) {
+            if keyframes.is_empty() {
+                if gain != 0.0 {
+                    filters.push(band.arg("g", gain));
+                }
+                continue;
+            }
+            let band = graph.instance(band.arg("g", gain_at(keyframes, 0.0)));
+            filters.push(send_commands(
+                band.name(),
+                "g",
+                &envelope_points(keyframes, beat),
+            ));
+            filters.push(band);
+        }
+        filters
+    }
+}
+
+/// beats between the gain commands of an envelope
+const ENVELOPE_STEP: f32 = 0.25;
+
+/// gain keyframes of each band, `[beat, dB]` from the start of the transition
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EqEnvelope {
+    pub low: Vec<(f32, f32)>,
+    pub mid: Vec<(f32, f32)>,
+    pub high: Vec<(f32, f32)>,
+}
+
+impl EqEnvelope {
+    pub fn is_empty(&self) -> bool {
+        self.bands().iter().all(|keyframes| keyframes.is_empty())
+    }
+
+    fn bands(&self) -> [&[(f32, f32)]; 3] {
+        [&self.low, &self.mid, &self.high]
+    }
+}
+
+/// dB at `beat`, linear between keyframes and held outside them
+fn gain_at(keyframes: &[(f32, f32)], beat: f32) -> f32 {
+    let next = keyframes.partition_point(|(b, _)| *b <= beat);
+    match (
+        next.checked_sub(1).map(|i| keyframes[i]),
+        keyframes.get(next),
+    ) {
+        (Some((b0, g0)), Some(&(b1, g1))) => g0 + (g1 - g0) * (beat - b0) / (b1 - b0),
+        (Some((_, gain)), None) | (None, Some(&(_, gain))) => gain,
+        (None, None) => 0.0,
+    }
+}
+
+/// `(seconds, dB)` every `ENVELOPE_STEP` beats and at each keyframe
+fn envelope_points(keyframes: &[(f32, f32)], beat: f32) -> Vec<(f32, f32)> {
+    let (Some(first), Some(last)) = (keyframes.first(), keyframes.last()) else {
+        return vec![];
+    };
+    let steps = ((last.0 - first.0) / ENVELOPE_STEP).ceil() as usize;
+    let mut beats = (0..steps)
+        .map(|i| first.0 + i as f32 * ENVELOPE_STEP)
+        .chain(keyframes.iter().map(|(b, _)| *b))
+        .collect::<Vec<_>>();
+    beats.sort_by(f32::total_cmp);
+    beats.dedup();
+    beats
+        .into_iter()
+        .map(|b| {
+            let gain = (gain_at(keyframes, b) * 100.0).round() / 100.0;
+            ((b * beat * 1000.0).round() / 1000.0, gain)
+        })
+        .collect()
+}
+
+/// crossfade from the end of a track into the next one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanTransition {
+    pub beats: u32,
+    #[serde(default, rename = "type")]
+    pub kind: TransitionKind,
+    /// of `type = "fade"`
+    #[serde(default)]
+    pub curve: FadeCurve,
+    /// eq of the outgoing track of fades
+    #[serde(default = "ThreeBandEq::bass_cut")]
+    pub out_eq: ThreeBandEq,
+    /// eq of the incoming track of fades
+    #[serde(default)]
+    pub in_eq: ThreeBandEq,
+    /// eq automation of the outgoing track
+    #[serde(default, skip_serializing_if = "EqEnvelope::is_empty")]
+    pub out_envelope: EqEnvelope,
+    /// eq automation of the incoming track
+    #[serde(default, skip_serializing_if = "EqEnvelope::is_empty")]
+    pub in_envelope: EqEnvelope,
+    /// milliseconds to move the incoming track later by, after aligning downbeats
+    #[serde(default)]
+    pub nudge_ms: f32,
+}
+
+impl PlanTransition {
+    pub fn new(beats: u32) -> Self {
+        Self {
+            beats,
+            kind: TransitionKind::default(),
+            curve: FadeCurve::default(),
+            out_eq: ThreeBandEq::bass_cut(),
+            in_eq: ThreeBandEq::default(),
+            out_envelope: EqEnvelope::default(),
+            in_envelope: EqEnvelope::default(),
+            nudge_ms: 0.0,
+        }
+    }
+}
+
+/// repeats the last `beats` beats before the end `times` more times
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoopExtension {
+    pub beats: u32,
+    pub times: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanTrack {
+    pub id: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// informational only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<Key>,
+    pub begin_hotcue: u8,
+    /// beats after `begin_hotcue`. must be 0 when the previous track has a
+    /// transition, the slice then starts `beats` of the transition after it
+    #[serde(default)]
+    pub begin_offset: i32,
+    pub end_hotcue: u8,
+    /// beats after `end_hotcue`
+    #[serde(default)]
+    pub end_offset: i32,
+    /// tempo at the start, defaults to the tempo the previous track ended with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bpm: Option<f32>,
+    /// tempo at the end
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_bpm: Option<f32>,
+    /// beats before the end the tempo ramps to `to_bpm` over, the whole slice by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ramp_beats: Option<u32>,
+    /// how the track is stretched to the mix tempo
+    #[serde(default, rename = "tempo")]
+    pub tempo_mode: TempoMode,
+    /// dB
+    #[serde(default)]
+    pub gain: f32,
+    #[serde(default, rename = "loop", skip_serializing_if = "Option::is_none")]
+    pub loop_extension: Option<LoopExtension>,
+    /// into the next track, a hard cut when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transition: Option<PlanTransition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MixPlan {
+    pub version: u32,
+    pub tracks: Vec<PlanTrack>,
+}
+
+/// problem at `tracks[track].keys`, or at the top level `keys`
+#[derive(Debug, PartialEq)]
+struct Invalid {
+    track: Option<usize>,
+    keys: Vec<&'static str>,
+    message: String,
+}
+
+impl Invalid {
+    fn new(track: Option<usize>, keys: &[&'static str], message: impl Into<String>) -> Self {
+        Self {
+            track,
+            keys: keys.to_vec(),
+            message: message.into(),
+        }
+    }
+
+    fn path(&self) -> String {
+        let keys = self.keys.iter().map(|key| key.to_string());
+        match self.track {
+            Some(i) => std::iter::once(format!("tracks[{}]", i))
+                .chain(keys)
+                .collect::<Vec<_>>()
+                .join("."),
+            None => keys.collect::<Vec<_>>().join("."),
+        }
+    }
+}
+
+/// the parsed file, to find the line of an invalid value
+enum Source<'a> {
+    Toml(ImDocument<&'a str>),
+    Json(&'a str),
+    /// csv line of each track
+    Csv(Vec<usize>),
+}
+
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// value of `key` when `raw` is an object
+fn json_field<'a>(raw: &'a RawValue, key: &str) -> Option<&'a RawValue> {
+    serde_json::from_str::<HashMap<String, &RawValue>>(raw.get())
+        .ok()?
+        .remove(key)
+}
+
+impl Source<'_> {
+    /// line of the deepest existing value on the path
+    fn line(&self, invalid: &Invalid) -> Option<usize> {
+        match self {
+            Self::Toml(doc) => {
+                let mut item = doc.as_item();
+                let mut span = None;
+                if let Some(i) = invalid.track {
+                    item = item.get("tracks")?;
+                    span = item.span().or(span);
+                    item = item.get(i)?;
+                    span = item.span().or(span);
+                }
+                for key in invalid.keys.iter() {
+                    match item.get(*key) {
+                        Some(value) => {
+                            item = value;
+                            span = item.span().or(span);
+                        }
+                        None => break,
+                    }
+                }
+                span.map(|span| line_at(doc.raw(), span.start))
+            }
+            Self::Json(text) => {
+                let mut value: &RawValue = serde_json::from_str(text).ok()?;
+                if let Some(i) = invalid.track {
+                    let tracks = json_field(value, "tracks")?;
+                    value = *serde_json::from_str::<Vec<&RawValue>>(tracks.get())
+                        .ok()?
+                        .get(i)?;
+                }
+                for key in invalid.keys.iter() {
+                    match json_field(value, key) {
+                        Some(field) => value = field,
+                        None => break,
+                    }
+                }
+                let offset = value.get().as_ptr() as usize - text.as_ptr() as usize;
+                Some(line_at(text, offset))
+            }
+            Self::Csv(lines) => invalid.track.and_then(|i| lines.get(i).copied()),
+        }
+    }
+}
+
+impl MixPlan {
+    /// converts legacy rows ordered by position.
+    /// their `begin_offset` and the crossfade of the last row were never rendered
+    pub fn from_legacy(mut tracks: Vec<MixTrack>) -> Result<Self> {
+        tracks.sort_by_key(|track| track.position);
+        if let Some(w) = tracks.windows(2).find(|w| w[0].position == w[1].position) {
+            anyhow::bail!("duplicated position {}", w[0].position);
+        }
+        let last = tracks.len().saturating_sub(1);
+        let tracks = tracks
+            .into_iter()
+            .enumerate()
+            .map(|(i, track)| PlanTrack {
+                id: track.id,
+                title: Some(track.title),
+                key: track.key,
+                begin_hotcue: track.begin_hotcue,
+                begin_offset: 0,
+                end_hotcue: track.end_hotcue,
+                end_offset: 0,
+                bpm: if i == 0 { track.bpm } else { None },
+                to_bpm: track.to_bpm,
+                ramp_beats: None,
+                tempo_mode: TempoMode::default(),
+                gain: 0.0,
+                loop_extension: None,
+                transition: (track.crossfade > 0 && i < last)
+                    .then(|| PlanTransition::new(track.crossfade as u32)),
+            })
+            .collect();
+        Ok(Self {
+            version: MIX_PLAN_VERSION,
+            tracks,
+        })
+    }
+
+    /// `render` adds what create-mix needs on top of a well formed plan
+    fn validate(&self, render: bool) -> Vec<Invalid> {
+        let mut invalids = vec![];
+        if self.version != MIX_PLAN_VERSION {
+            invalids.push(Invalid::new(
+                None,
+                &["version"],
+                format!(
+                    "unsupported version {}, expected {}",
+                    self.version, MIX_PLAN_VERSION
+                ),
+            ));
+        }
+        if render && self.tracks.len() < 2 {
+            invalids.push(Invalid::new(None, &["tracks"], "needs at least 2 tracks"));
+        }
+        if render && self.tracks.first().is_some_and(|track| track.bpm.is_none()) {
+            invalids.push(Invalid::new(Some(0), &[], "the first track needs bpm"));
+        }
+        for (i, track) in self.tracks.iter().enumerate() {
+            let incoming = i
+                .checked_sub(1)
+                .is_some_and(|prev| self.tracks[prev].transition.is_some());
+            let mut check = |ok: bool, keys: &[&'static str], message: &str| {
+                if !ok {
+                    invalids.push(Invalid::new(Some(i), keys, message));
+                }
+            };
+            let positive = |bpm: Option<f32>| bpm.is_none_or(|bpm| bpm.is_finite() && bpm > 0.0);
+            check(positive(track.bpm), &["bpm"], "must be positive");
+            check(
+                !incoming || track.begin_offset == 0,
+                &["begin_offset"],
+                "must be 0 after a transition",
+            );
+            check(positive(track.to_bpm), &["to_bpm"], "must be positive");
+            check(
+                track.ramp_beats.is_none_or(|beats| beats > 0),
+                &["ramp_beats"],
+                "must be positive",
+            );
+            check(
+                track.ramp_beats.is_none() || track.to_bpm.is_some(),
+                &["ramp_beats"],
+                "needs to_bpm",
+            );
+            check(track.gain.is_finite(), &["gain"], "must be finite");
+            if let Some(loop_extension) = &track.loop_extension {
+                check(
+                    loop_extension.beats > 0,
+                    &["loop", "beats"],
+                    "must be positive",
+                );
+                check(
+                    loop_extension.times > 0,
+                    &["loop", "times"],
+                    "must be positive",
+                );
+            }
+            if let Some(transition) = &track.transition {
+                check(
+                    transition.beats > 0,
+                    &["transition", "beats"],
+                    "must be positive, omit the transition for a cut",
+                );
+                for (eq, name) in [(transition.out_eq, "out_eq"), (transition.in_eq, "in_eq")] {
+                    let finite = [eq.low, eq.mid, eq.high].iter().all(|g| g.is_finite());
+                    check(finite, &["transition", name], "gains must be finite");
+                }
+                check(
+                    transition.nudge_ms.is_finite(),
+                    &["transition", "nudge_ms"],
+                    "must be finite",
+                );
+                for (envelope, name) in [
+                    (&transition.out_envelope, "out_envelope"),
+                    (&transition.in_envelope, "in_envelope"),
+                ] {
+                    let bands = envelope.bands();
+                    let finite = bands
+                        .iter()
+                        .flat_map(|k| k.iter())
+                        .all(|(_, g)| g.is_finite());
+                    check(finite, &["transition", name], "gains must be finite");
+                    let beats = 0.0..=transition.beats as f32;
+                    let in_order = bands.iter().all(|keyframes| {
+                        keyframes.iter().all(|(b, _)| beats.contains(b))
+                            && keyframes.windows(2).all(|w| w[0].0 < w[1].0)
+                    });
+                    check(
+                        in_order,
+                        &["transition", name],
+                        "keyframe beats must increase within the transition",
+                    );
+                }
+                check(
+                    i + 1 < self.tracks.len(),
+                    &["transition"],
+                    "the last track has no next track",
+                );
+            }
+        }
+        invalids
+    }
+
+    fn checked(self, source: &Source, render: bool) -> Result<Self> {
+        let invalids = self.validate(render);
+        if invalids.is_empty() {
+            return Ok(self);
+        }
+        let messages = invalids
+            .iter()
+            .map(|invalid| match source.line(invalid) {
+                Some(line) => format!("line {}: {}: {}", line, invalid.path(), invalid.message),
+                None => format!("{}: {}", invalid.path(), invalid.message),
+            })
+            .collect::<Vec<_>>();
+        anyhow::bail!("invalid mix plan\n{}", messages.join("\n"))
+    }
+
+    pub fn parse(text: &str, format: PlanFormat) -> Result<Self> {
+        Self::parse_checked(text, format, false)
+    }
+
+    /// also checks the plan can be rendered
+    pub fn parse_for_render(text: &str, format: PlanFormat) -> Result<Self> {
+        Self::parse_checked(text, format, true)
+    }
+
+    fn parse_checked(text: &str, format: PlanFormat, render: bool) -> Result<Self> {
+        match format {
+            PlanFormat::Toml => {
+                let plan: Self = toml::from_str(text)?;
+                plan.checked(&Source::Toml(ImDocument::parse(text)?), render)
+            }
+            PlanFormat::Json => {
+                let plan: Self = serde_json::from_str(text)?;
+                plan.checked(&Source::Json(text), render)
+            }
+            PlanFormat::Csv => {
+                let rows = csv::Reader::from_reader(text.as_bytes())
+                    .deserialize()
+                    .collect::<Result<Vec<MixTrack>, _>>()?;
+                let mut lines = rows
+                    .iter()
+                    .enumerate()
+                    // below the header
+                    .map(|(i, track)| (track.position, i + 2))
+                    .collect::<Vec<_>>();
+                lines.sort_by_key(|(position, _)| *position);
+                let lines = lines.into_iter().map(|(_, line)| line).collect();
+                Self::from_legacy(rows)?.checked(&Source::Csv(lines), render)
+            }
+        }
+    }
+
+    /// reads `.toml`, `.json` or a legacy csv
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text, PlanFormat::from_path(path))
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn read_for_render(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse_for_render(&text, PlanFormat::from_path(path))
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let text = match PlanFormat::from_path(path) {
+            PlanFormat::Toml => toml::to_string_pretty(self)?,
+            PlanFormat::Json => serde_json::to_string_pretty(self)?,
+            PlanFormat::Csv => anyhow::bail!("csv is a legacy input, write .toml or .json"),
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        EqEnvelope, FadeCurve, LoopExtension, MixPlan, PlanFormat, PlanTransition, ThreeBandEq,
+    };
+    use crate::ffmpeg::filter_graph::FilterGraph;
+
+    const TOML: &str = r#"version = 1
+
+[[tracks]]
+id = 1
+begin_hotcue = 0
+end_hotcue = 3
+bpm = 124.0
+gain = -2.0
+loop = { beats = 8, times = 2 }
+
+[tracks.transition]
+beats = 32
+curve = "tri"
+out_eq = { low = -20.0 }
+in_envelope = { low = [[0, -30.0], [16, 0.0]] }
+
+[[tracks]]
+id = 2
+begin_hotcue = 1
+end_hotcue = 4
+"#;
+
+    const JSON: &str = r#"{
+  "version": 1,
+  "tracks": [
+    {
+      "id": 1,
+      "begin_hotcue": 0,
+      "end_hotcue": 3,
+      "bpm": 124.0,
+      "gain": -2.0,
+      "loop": { "beats": 8, "times": 2 },
+      "transition": {
+        "beats": 32,
+        "curve": "tri",
+        "out_eq": { "low": -20.0 },
+        "in_envelope": { "low": [[0, -30.0], [16, 0.0]] }
+      }
+    },
+    { "id": 2, "begin_hotcue": 1, "end_hotcue": 4 }
+  ]
+}"#;
+
+    #[test]
+    fn test_parse_mix_plan() {
+        let plan = MixPlan::parse(TOML, PlanFormat::Toml).unwrap();
+        assert_eq!(plan, MixPlan::parse(JSON, PlanFormat::Json).unwrap());
+        assert_eq!(
+            plan.tracks[0].loop_extension,
+            Some(LoopExtension { beats: 8, times: 2 })
+        );
+        assert_eq!(
+            plan.tracks[0].transition,
+            Some(PlanTransition {
+                curve: FadeCurve::Tri,
+                out_eq: ThreeBandEq {
+                    low: -20.0,
+                    ..Default::default()
+                },
+                in_envelope: EqEnvelope {
+                    low: vec![(0.0, -30.0), (16.0, 0.0)],
+                    ..Default::default()
+                },
+                ..PlanTransition::new(32)
+            })
+        );
+        assert_eq!(
+            ThreeBandEq::bass_cut()
+                .automate(&EqEnvelope::default(), 0.5, &mut FilterGraph::new())
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>(),
+            vec!["equalizer=f=300:t=h:width=200:g=-10"]
+        );
+        let envelope = EqEnvelope {
+            high: vec![(0.0, 0.0), (0.5, -6.0)],
+            ..Default::default()
+        };
+        assert_eq!(
+            ThreeBandEq::bass_cut()
+                .automate(&envelope, 0.5, &mut FilterGraph::new())
+                .iter()
+                .map(|f| f.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "equalizer=f=300:t=h:width=200:g=-10",
+                r"asendcmd=commands=0 treble@i1 g 0\;0.125 treble@i1 g -3\;0.25 treble@i1 g -6",
+                "treble@i1=f=4000:g=0",
+            ]
+        );
+        assert_eq!(plan.tracks[1].bpm, None);
+        assert_eq!(plan.tracks[1].transition, None);
+        let toml = toml::to_string_pretty(&plan).unwrap();
+        assert_eq!(MixPlan::parse(&toml, PlanFormat::Toml).unwrap(), plan);
+    }
+
+    #[test]
+    fn test_invalid_mix_plan() {
+        let toml = TOML
+            .replace("version = 1", "version = 2")
+            .replace("times = 2", "times = 0")
+            .replace("[16, 0.0]", "[40, 0.0]")
+            .replace(
+                "end_hotcue = 4",
+                "end_hotcue = 4\nto_bpm = -1.0\ntransition = { beats = 4 }",
+            )
+            .replace("begin_hotcue = 1", "begin_hotcue = 1\nbegin_offset = 8");
+        let err = MixPlan::parse_for_render(&toml, PlanFormat::Toml).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            [
+                "invalid mix plan",
+                "line 1: version: unsupported version 2, expected 1",
+                "line 9: tracks[0].loop.times: must be positive",
+                "line 15: tracks[0].transition.in_envelope: keyframe beats must increase within the transition",
+                "line 20: tracks[1].begin_offset: must be 0 after a transition",
+                "line 22: tracks[1].to_bpm: must be positive",
+                "line 23: tracks[1].transition: the last track has no next track",
+            ]
+            .join("\n")
+        );
+
+        let json = JSON
+            .replace(r#""bpm": 124.0,"#, "")
+            .replace(r#""beats": 32"#, r#""beats": 0"#);
+        let err = MixPlan::parse_for_render(&json, PlanFormat::Json).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            [
+                "invalid mix plan",
+                "line 4: tracks[0]: the first track needs bpm",
+                "line 12: tracks[0].transition.beats: must be positive, omit the transition for a cut",
+                "line 15: tracks[0].transition.in_envelope: keyframe beats must increase within the transition",
+            ]
+            .join("\n")
+        );
+
+        // a string value named like the invalid key
+        let json = JSON
+            .replace(r#""id": 1,"#, r#""id": 1, "title": "bpm","#)
+            .replace(r#""bpm": 124.0,"#, r#""bpm": -1.0,"#);
+        let err = MixPlan::parse(&json, PlanFormat::Json).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("line 8: tracks[0].bpm: must be positive"));
+
+        let err = MixPlan::parse(
+            "version = 1\n[[tracks]]\nid = 1\nbegin_hotcue = 0\n",
+            PlanFormat::Toml,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+
+        // enough to write a playlist, not to render
+        let one = "version = 1\n[[tracks]]\nid = 1\nbegin_hotcue = 0\nend_hotcue = 3\n";
+        assert!(MixPlan::parse(one, PlanFormat::Toml).is_ok());
+        let err = MixPlan::parse_for_render(one, PlanFormat::Toml).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            [
+                "invalid mix plan",
+                "line 2: tracks: needs at least 2 tracks",
+                "line 2: tracks[0]: the first track needs bpm",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_legacy_mix_plan() {
+        let csv =
+            "position,id,title,key,begin_hotcue,begin_offset,end_hotcue,bpm,to_bpm,crossfade\n\
+            2,20,b,,1,33,4,,,0\n\
+            1,10,a,8A,0,0,3,124.0,126.0,32\n\
+            3,30,c,,1,33,4,,,32\n";
+        let plan = MixPlan::parse(csv, PlanFormat::Csv).unwrap();
+        assert_eq!(
+            plan.tracks.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![10, 20, 30]
+        );
+        assert_eq!(plan.tracks[0].transition, Some(PlanTransition::new(32)));
+        assert_eq!(plan.tracks[0].to_bpm, Some(126.0));
+        assert_eq!(plan.tracks[1].begin_offset, 0);
+        assert_eq!(plan.tracks[1].transition, None);
+        assert_eq!(plan.tracks[2].transition, None);
+
+        let err = MixPlan::parse(&csv.replace("124.0", "-1"), PlanFormat::Csv).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("line 3: tracks[0].bpm: must be positive"));
+    }
+}
@@ -1,46 +1,75 @@
 use self::{
+    cache::{cache_command, CacheArgs},
     converter::{convert_track_locations, ConvertArgs},
+    crates::{list_crate_tracks, CrateArgs},
     cross_fade::{CrossFadeArgs, CrossFadeCommand},
-    mix::{CreateMixArgs, MixList, MixTrack},
-    playlist::{list_playlist_tracks, PlaylistArgs},
+    mix::{CreateMixArgs, MixList},
+    mix_plan::MixPlan,
+    playlist::{playlist_command, PlaylistArgs},
+    rekordbox::{export_rekordbox, RekordboxArgs},
     slice::{SliceArgs, SliceCommand},
+    suggest_order::{suggest_order, SuggestOrderArgs},
     tag::list_mp3_tag,
+    traktor::{traktor_command, TraktorArgs},
 };
 use anyhow::Result;
 use clap::Parser;
 use rusqlite::Connection;
 use std::path::PathBuf;
 
+pub mod cache;
 pub mod converter;
+pub mod crates;
 pub mod cross_fade;
 pub mod mix;
+pub mod mix_plan;
 pub mod playlist;
+pub mod rekordbox;
 pub mod slice;
+pub mod suggest_order;
 pub mod tag;
+pub mod tracklist;
+pub mod traktor;
+pub mod transition;
 pub mod utils;
+pub mod work_dir;
 
 #[derive(Debug, clap::Parser)]
 enum MixxxCli {
     Playlist(PlaylistArgs),
+    Crate(CrateArgs),
     Convert(ConvertArgs),
     CrossFade(CrossFadeArgs),
     Slice(SliceArgs),
+    /// render a mix plan to one file
     CreateMix(CreateMixArgs),
+    SuggestOrder(SuggestOrderArgs),
+    /// export playlists and crates as rekordbox XML
+    Rekordbox(RekordboxArgs),
+    /// export NML and import cue points from NML
+    Traktor(TraktorArgs),
+    /// list and remove rendered mix segments
+    Cache(CacheArgs),
     Tag,
 }
 
 pub fn handle_commands() -> Result<()> {
     let args = MixxxCli::try_parse()?;
+    // needs no database
+    if let MixxxCli::Cache(args) = &args {
+        return cache_command(args);
+    }
 
     let db_path: String = std::env::var("MIXXX_DB_PATH")?;
     let db_path = PathBuf::from(db_path);
     let conn = Connection::open(db_path)?;
 
     match args {
-        MixxxCli::Playlist(args) => list_playlist_tracks(&conn, &args),
+        MixxxCli::Playlist(args) => playlist_command(&conn, &args),
+        MixxxCli::Crate(args) => list_crate_tracks(&conn, &args),
         MixxxCli::Convert(args) => convert_track_locations(&conn, &args),
         MixxxCli::CrossFade(args) => {
-            let cmd = CrossFadeCommand::new(
+            let mut cmd = CrossFadeCommand::new(
                 &conn,
                 args.a_id,
                 args.a_hotcue,
@@ -49,10 +78,13 @@ pub fn handle_commands() -> Result<()> {
                 args.crossfade,
                 args.bpm,
             )?;
+            cmd.nudge_ms = args.nudge_ms;
+            cmd.a_tempo_mode = args.a_tempo_mode;
+            cmd.b_tempo_mode = args.b_tempo_mode;
             cmd.execute(&args.out)
         }
         MixxxCli::Slice(args) => {
-            let cmd = SliceCommand::new(
+            let mut cmd = SliceCommand::new(
                 &conn,
                 args.id,
                 args.from_hotcue,
@@ -62,15 +94,19 @@ pub fn handle_commands() -> Result<()> {
                 args.bpm,
                 args.to_bpm,
             )?;
+            cmd.ramp_beats = args.ramp_beats;
+            cmd.tempo_mode = args.tempo_mode;
             cmd.execute(&args.out)
         }
         MixxxCli::CreateMix(args) => {
-            let tracks = csv::Reader::from_path(&args.input)?
-                .deserialize()
-                .collect::<Result<Vec<MixTrack>, _>>()?;
-            let mix = MixList::from_tracks(&conn, &tracks)?;
-            mix.execute(&args.out)
+            let plan = MixPlan::read_for_render(&args.input)?;
+            let mut mix = MixList::from_plan(&conn, &plan)?;
+            mix.execute(&args)
         }
+        MixxxCli::SuggestOrder(args) => suggest_order(&conn, &args),
+        MixxxCli::Rekordbox(args) => export_rekordbox(&conn, &args),
+        MixxxCli::Traktor(args) => traktor_command(&conn, &args),
+        MixxxCli::Cache(_) => unreachable!(),
         MixxxCli::Tag => list_mp3_tag(&conn),
     }
 }
@@ -1,18 +1,86 @@
-use super::mix::MixTrack;
-use crate::mixxx::{
-    cue::Cue, library::Library, playlist::Playlist, playlist_track::PlaylistTrack, repo::AsRepo,
+use super::{
+    mix::MixTrack,
+    mix_plan::{MixPlan, PlanFormat},
+};
+use crate::{
+    export::{ExportPlaylist, PlaylistFormat},
+    mixxx::{
+        cue::Cue,
+        key::{Key, KeyNotation},
+        library::Library,
+        playlist::Playlist,
+        playlist_track::PlaylistTrack,
+        repo::AsRepo,
+    },
 };
 use anyhow::Result;
 use comfy_table::Table;
 use rusqlite::Connection;
-use std::{collections::BTreeMap, path::PathBuf, time::Duration};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 #[derive(Debug, clap::Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct PlaylistArgs {
+    #[command(subcommand)]
+    command: Option<PlaylistCommand>,
+    #[command(flatten)]
+    list: ListPlaylistArgs,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum PlaylistCommand {
+    /// list playlist's tracks (default)
+    List(ListPlaylistArgs),
+    /// create or rewrite a playlist from a mix plan or CSV
+    Write(WritePlaylistArgs),
+    /// delete a playlist and its tracks
+    Delete(DeletePlaylistArgs),
+    /// write a playlist file for other players
+    Export(ExportPlaylistArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ListPlaylistArgs {
     #[arg(long)]
-    playlist_id: i32,
+    playlist_id: Option<i32>,
     #[arg(long)]
     out: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = KeyNotation::Lancelot)]
+    key_notation: KeyNotation,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct WritePlaylistArgs {
+    input: PathBuf,
+    /// playlist to rewrite
+    #[arg(long, required_unless_present = "name", conflicts_with = "name")]
+    playlist_id: Option<i32>,
+    /// name of a new playlist
+    #[arg(long)]
+    name: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DeletePlaylistArgs {
+    #[arg(long)]
+    playlist_id: i32,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ExportPlaylistArgs {
+    #[arg(long)]
+    playlist_id: i32,
+    #[arg(long, value_enum)]
+    format: PlaylistFormat,
+    /// write paths relative to this directory instead of absolute ones
+    #[arg(long)]
+    base_dir: Option<PathBuf>,
+    #[arg(long)]
+    out: PathBuf,
 }
 
 #[derive(Debug)]
@@ -22,6 +90,7 @@ pub struct TrackModel {
     pub title: String,
     pub artist: Option<String>,
     pub bpm: f32,
+    pub key: Option<Key>,
     pub cues: BTreeMap<u8, Duration>,
 }
 
@@ -42,7 +111,7 @@ pub struct PlaylistModel {
 }
 
 impl PlaylistModel {
-    fn into_mix_tracks(&self) -> Vec<MixTrack> {
+    pub fn into_mix_tracks(self) -> Vec<MixTrack> {
         let mut mix_tracks: Vec<MixTrack> = vec![];
         let mut current_bpm: f32 = 0.;
         for (i, track) in self.tracks.iter().enumerate() {
@@ -50,6 +119,7 @@ impl PlaylistModel {
                 track.position as usize,
                 track.track_id,
                 track.title.clone(),
+                track.key,
                 track.first_cue().unwrap_or(0),
                 if i == 0 { 0 } else { 32 + 1 },
                 track.last_cue().unwrap_or(0),
@@ -67,34 +137,36 @@ impl PlaylistModel {
     }
 }
 
-fn fetch_track(conn: &Connection, playlist_track: &PlaylistTrack) -> Result<TrackModel> {
+pub fn fetch_track(conn: &Connection, track_id: i32, position: i32) -> Result<TrackModel> {
     let lib_repo = Library::repo(conn);
     let cue_repo = Cue::repo(conn);
 
     let library = lib_repo
-        .select(playlist_track.track_id)?
+        .select(track_id)?
         .ok_or(anyhow::anyhow!("track not found"))?;
+    let key = Key::from_library(&library)?;
     let cues = cue_repo
-        .hot_cues_by_track_id(playlist_track.track_id)?
+        .hot_cues_by_track_id(track_id)?
         .iter()
         .map(|cue| {
             (
-                cue.hotcue,
+                cue.hotcue as u8,
                 Duration::from_secs_f32(cue.position.max(0.) / library.samplerate as f32 / 2.0),
             )
         })
         .collect::<BTreeMap<_, _>>();
     Ok(TrackModel {
         track_id: library.id,
-        position: playlist_track.position,
+        position,
         title: library.title,
         artist: library.artist,
         bpm: library.bpm,
+        key,
         cues,
     })
 }
 
-fn fetch_playlist(conn: &Connection, id: i32) -> Result<PlaylistModel> {
+pub fn fetch_playlist(conn: &Connection, id: i32) -> Result<PlaylistModel> {
     let playlist_repo = Playlist::repo(conn);
     let playlist_track_repo = PlaylistTrack::repo(conn);
 
@@ -104,7 +176,7 @@ fn fetch_playlist(conn: &Connection, id: i32) -> Result<PlaylistModel> {
     let tracks = playlist_track_repo
         .find_by_playlist_id(id)?
         .iter()
-        .map(|playlist_track| fetch_track(conn, playlist_track))
+        .map(|playlist_track| fetch_track(conn, playlist_track.track_id, playlist_track.position))
         .collect::<Result<Vec<_>>>()?;
     Ok(PlaylistModel {
         title: playlist.name,
@@ -112,11 +184,12 @@ fn fetch_playlist(conn: &Connection, id: i32) -> Result<PlaylistModel> {
     })
 }
 
-pub fn list_playlist_tracks(conn: &Connection, args: &PlaylistArgs) -> Result<()> {
-    let playlist = fetch_playlist(conn, args.playlist_id)?;
-
+pub fn print_tracks(playlist: &PlaylistModel, key_notation: KeyNotation) {
+    println!("{}", playlist.title);
     let mut table = Table::new();
-    table.set_header(vec!["#", "track_id", "bpm", "title", "artist", "cues"]);
+    table.set_header(vec![
+        "#", "track_id", "bpm", "key", "title", "artist", "cues",
+    ]);
     for track in playlist.tracks.iter() {
         let artists = track
             .artist
@@ -127,6 +200,10 @@ pub fn list_playlist_tracks(conn: &Connection, args: &PlaylistArgs) -> Result<()
             track.position.to_string(),
             track.track_id.to_string(),
             track.bpm.to_string(),
+            track
+                .key
+                .map(|key| key.to_notation(key_notation))
+                .unwrap_or("---".to_string()),
             track.title.chars().take(25).collect(),
             artists.chars().take(15).collect(),
             track
@@ -138,14 +215,132 @@ pub fn list_playlist_tracks(conn: &Connection, args: &PlaylistArgs) -> Result<()
         ]);
     }
     println!("{}", table);
+}
 
+/// writes a mix plan for `.toml` and `.json`, a legacy mix csv otherwise
+pub fn write_mix_tracks(playlist: PlaylistModel, out: &Path) -> Result<()> {
+    let mix_tracks = playlist.into_mix_tracks();
+    if PlanFormat::from_path(out) != PlanFormat::Csv {
+        return MixPlan::from_legacy(mix_tracks)?.write(out);
+    }
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for track in mix_tracks.iter() {
+        writer.serialize(track)?;
+    }
+    std::fs::write(out, writer.into_inner()?)?;
+    Ok(())
+}
+
+pub fn list_playlist_tracks(conn: &Connection, args: &ListPlaylistArgs) -> Result<()> {
+    let id = args
+        .playlist_id
+        .ok_or(anyhow::anyhow!("--playlist-id is required"))?;
+    let playlist = fetch_playlist(conn, id)?;
+    print_tracks(&playlist, args.key_notation);
     if let Some(out) = &args.out {
-        let mix_tracks = playlist.into_mix_tracks();
-        let mut writer = csv::Writer::from_writer(vec![]);
-        for track in mix_tracks.iter() {
-            writer.serialize(track)?;
+        write_mix_tracks(playlist, out)?;
+    }
+    Ok(())
+}
+
+fn fetch_unlocked_playlist(conn: &Connection, id: i32) -> Result<Playlist> {
+    let playlist = Playlist::repo(conn)
+        .select(id)?
+        .ok_or(anyhow::anyhow!("playlist id={} not found", id))?;
+    if playlist.locked {
+        anyhow::bail!("playlist {} is locked", playlist.name);
+    }
+    Ok(playlist)
+}
+
+/// replaces the playlist's tracks with the mix plan's tracks.
+/// rows of tracks already in the playlist are kept to preserve their added date
+pub fn write_playlist(conn: &Connection, args: &WritePlaylistArgs) -> Result<()> {
+    let mix_tracks = MixPlan::read(&args.input)?.tracks;
+
+    let tx = conn.unchecked_transaction()?;
+    let lib_repo = Library::repo(&tx);
+    let playlist_repo = Playlist::repo(&tx);
+    let playlist_track_repo = PlaylistTrack::repo(&tx);
+
+    let playlist_id = match (args.playlist_id, &args.name) {
+        (Some(id), _) => {
+            let playlist = fetch_unlocked_playlist(&tx, id)?;
+            playlist_repo.update(&playlist)?;
+            id
         }
-        std::fs::write(out, writer.into_inner()?)?;
+        (_, Some(name)) => playlist_repo.insert(name)?,
+        _ => anyhow::bail!("--playlist-id or --name is required"),
+    };
+    let mut existing = playlist_track_repo
+        .find_by_playlist_id(playlist_id)?
+        .into_iter()
+        .map(Some)
+        .collect::<Vec<_>>();
+    for (i, mix_track) in mix_tracks.iter().enumerate() {
+        lib_repo
+            .select(mix_track.id)?
+            .ok_or(anyhow::anyhow!("track id={} not found", mix_track.id))?;
+        let position = i as i32 + 1;
+        let reused = existing
+            .iter_mut()
+            .find(|t| matches!(t, Some(t) if t.track_id == mix_track.id))
+            .and_then(Option::take);
+        match reused {
+            Some(playlist_track) => playlist_track_repo.update(&PlaylistTrack {
+                position,
+                ..playlist_track
+            })?,
+            None => {
+                playlist_track_repo.insert(&PlaylistTrack {
+                    id: 0,
+                    playlist_id,
+                    track_id: mix_track.id,
+                    position,
+                })?;
+            }
+        }
+    }
+    for playlist_track in existing.into_iter().flatten() {
+        playlist_track_repo.delete(playlist_track.id)?;
     }
+    tx.commit()?;
+    log::debug!(
+        "{} tracks written to playlist id={}",
+        mix_tracks.len(),
+        playlist_id
+    );
+    Ok(())
+}
+
+pub fn delete_playlist(conn: &Connection, args: &DeletePlaylistArgs) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    let playlist = fetch_unlocked_playlist(&tx, args.playlist_id)?;
+    PlaylistTrack::repo(&tx).delete_by_playlist_id(playlist.id)?;
+    Playlist::repo(&tx).delete(playlist.id)?;
+    tx.commit()?;
+    log::debug!("playlist {} deleted", playlist.name);
     Ok(())
 }
+
+pub fn export_playlist(conn: &Connection, args: &ExportPlaylistArgs) -> Result<()> {
+    let playlist = ExportPlaylist::fetch(conn, args.playlist_id)?;
+    let content = playlist.render(args.format, args.base_dir.as_deref())?;
+    std::fs::write(&args.out, content)?;
+    log::debug!(
+        "{} tracks exported to {}",
+        playlist.tracks.len(),
+        args.out.display()
+    );
+    Ok(())
+}
+
+pub fn playlist_command(conn: &Connection, args: &PlaylistArgs) -> Result<()> {
+    match &args.command {
+        Some(PlaylistCommand::List(args)) => list_playlist_tracks(conn, args),
+        Some(PlaylistCommand::Write(args)) => write_playlist(conn, args),
+        Some(PlaylistCommand::Delete(args)) => delete_playlist(conn, args),
+        Some(PlaylistCommand::Export(args)) => export_playlist(conn, args),
+        None => list_playlist_tracks(conn, &args.list),
+    }
+}
@@ -0,0 +1,37 @@
+use crate::export::{rekordbox, ExportPlaylist};
+use anyhow::Result;
+use rusqlite::Connection;
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Parser)]
+pub struct RekordboxArgs {
+    #[arg(long)]
+    playlist_id: Vec<i32>,
+    #[arg(long)]
+    crate_id: Vec<i32>,
+    #[arg(long)]
+    out: PathBuf,
+}
+
+pub fn export_rekordbox(conn: &Connection, args: &RekordboxArgs) -> Result<()> {
+    if args.playlist_id.is_empty() && args.crate_id.is_empty() {
+        anyhow::bail!("--playlist-id or --crate-id is required");
+    }
+    let playlists = args
+        .playlist_id
+        .iter()
+        .map(|id| ExportPlaylist::fetch(conn, *id))
+        .chain(
+            args.crate_id
+                .iter()
+                .map(|id| ExportPlaylist::fetch_crate(conn, *id)),
+        )
+        .collect::<Result<Vec<_>>>()?;
+    std::fs::write(&args.out, rekordbox::render(&playlists)?)?;
+    log::debug!(
+        "{} playlists exported to {}",
+        playlists.len(),
+        args.out.display()
+    );
+    Ok(())
+}
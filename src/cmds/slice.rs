@@ -1,7 +1,15 @@
+use super::mix_plan::LoopExtension;
 use crate::{
-    cmds::utils::{cue_at, get_hotcue, get_track},
-    ffmpeg::{slice_cmd, stepped_tempo_filter::SteppedTempoFilter},
-    mixxx::{cue::Cue, library::Library},
+    cmds::utils::{check_downbeat, cue_downbeat, get_hotcue, get_track},
+    ffmpeg::{
+        encoder::Encoding,
+        ffmpeg_complex_filter,
+        filter_graph::{Filter, FilterGraph, Pad},
+        progress::{seconds_bar, update_seconds},
+        stepped_tempo_filter::{check_tempo_modes, SteppedTempoFilter, TempoMode},
+        Input,
+    },
+    mixxx::{beats::BeatGrid, cue::Cue, library::Library},
 };
 use anyhow::Result;
 use rusqlite::Connection;
@@ -23,6 +31,11 @@ pub struct SliceArgs {
     pub bpm: f32,
     #[arg(long)]
     pub to_bpm: Option<f32>,
+    /// beats before the end the tempo ramps to --to-bpm over, the whole slice by default
+    #[arg(long, requires = "to_bpm")]
+    pub ramp_beats: Option<u32>,
+    #[arg(long, value_enum, default_value_t)]
+    pub tempo_mode: TempoMode,
     #[arg(long)]
     pub out: PathBuf,
 }
@@ -31,13 +44,23 @@ pub struct SliceArgs {
 pub struct SliceCommand {
     a_path: PathBuf,
     a: Library,
+    a_grid: BeatGrid,
     from: (Cue, i32),
     to: (Cue, i32),
     bpm: f32,
     to_bpm: Option<f32>,
+    /// beats before the end the tempo ramps to `to_bpm` over, all when `None`
+    pub ramp_beats: Option<u32>,
+    pub tempo_mode: TempoMode,
+    /// dB
+    pub gain: f32,
+    pub loop_extension: Option<LoopExtension>,
+    /// single-pass loudnorm of the slice, off when `gain` normalizes the track
+    pub loudnorm: bool,
 }
 
 impl SliceCommand {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         conn: &Connection,
         track_id: i32,
@@ -49,17 +72,26 @@ impl SliceCommand {
         to_bpm: Option<f32>,
     ) -> Result<Self> {
         let (a_path, a) = get_track(conn, track_id)?;
+        let a_grid = BeatGrid::from_library(&a)?;
         let from_cue = get_hotcue(conn, track_id, from_hotcue)?;
         let to_cue = get_hotcue(conn, track_id, to_hotcue)?;
+        check_downbeat(&a, &a_grid, &from_cue);
+        check_downbeat(&a, &a_grid, &to_cue);
         let from = (from_cue, from_offset);
         let to = (to_cue, to_offset);
         Ok(Self {
             a_path,
             a,
+            a_grid,
             from,
             to,
             bpm,
             to_bpm,
+            ramp_beats: None,
+            tempo_mode: TempoMode::default(),
+            gain: 0.0,
+            loop_extension: None,
+            loudnorm: true,
         })
     }
 
@@ -70,35 +102,180 @@ impl SliceCommand {
         )
     }
 
-    pub fn execute(&self, out: &Path) -> Result<()> {
+    pub fn path(&self) -> &Path {
+        &self.a_path
+    }
+
+    pub fn track(&self) -> &Library {
+        &self.a
+    }
+
+    /// seconds of the source track, from and to the downbeats of the cues
+    fn range(&self) -> (f32, f32) {
         let (from_hotcue, from_offset) = &self.from;
         let (to_hotcue, to_offset) = &self.to;
-        println!(
-            "@{}+{} .. @{}+{}",
-            from_hotcue.hotcue, from_offset, to_hotcue.hotcue, to_offset
-        );
+        (
+            self.a_grid.shift(
+                cue_downbeat(&self.a, &self.a_grid, from_hotcue),
+                *from_offset as f32,
+            ),
+            self.a_grid.shift(
+                cue_downbeat(&self.a, &self.a_grid, to_hotcue),
+                *to_offset as f32,
+            ),
+        )
+    }
 
-        let beat = 60.0 / self.a.bpm;
-        let a_range = (
-            cue_at(&self.a, from_hotcue) + beat * *from_offset as f32,
-            cue_at(&self.a, to_hotcue) + beat * *to_offset as f32,
-        );
-        let (f, t) = (0.0, a_range.1 - a_range.0);
-        // let (f, t) = a_range;
-        let a_scale = if let Some(to_bpm) = self.to_bpm {
-            let from_scale = self.bpm / self.a.bpm;
-            let to_scale = to_bpm / self.a.bpm;
-            SteppedTempoFilter::new((f, from_scale), (t, to_scale), 4)
-        } else {
-            let scale = self.bpm / self.a.bpm;
-            SteppedTempoFilter::new((f, scale), (t, scale), 1)
+    /// seconds of the source track to loop before the end, and how many more times
+    fn loop_span(&self) -> Option<(f32, u32)> {
+        self.loop_extension.map(|l| {
+            let end = self.range().1;
+            (end - self.a_grid.shift(end, -(l.beats as f32)), l.times)
+        })
+    }
+
+    fn tempo_filter(&self) -> SteppedTempoFilter {
+        let (begin, end) = self.range();
+        let (looped, looped_beats) = self
+            .loop_span()
+            .zip(self.loop_extension)
+            .map(|((length, times), l)| (length * times as f32, l.beats * times))
+            .unwrap_or((0.0, 0));
+        let length = end - begin + looped;
+        let from_scale = self.bpm / self.a.bpm;
+        let Some(to_bpm) = self.to_bpm else {
+            return SteppedTempoFilter::constant(0.0, length, from_scale).mode(self.tempo_mode);
         };
-        println!(
-            "bpm={} target_bpm={} tempo={:?}",
-            self.a.bpm, self.bpm, a_scale
+        let slice_beats =
+            self.a_grid.beat_at(end) - self.a_grid.beat_at(begin) + looped_beats as f32;
+        let ramp_beats = self
+            .ramp_beats
+            .unwrap_or(u32::MAX)
+            .min(slice_beats.floor().max(1.0) as u32);
+        // seconds of each beat of the ramp, counted back from the end
+        let beats = (0..=ramp_beats)
+            .map(|i| {
+                let before_end = end - self.a_grid.shift(end, -((ramp_beats - i) as f32));
+                (length - before_end).max(0.0)
+            })
+            .collect::<Vec<_>>();
+        SteppedTempoFilter::ramp(0.0, &beats, from_scale, to_bpm / self.a.bpm).mode(self.tempo_mode)
+    }
+
+    /// logs the semitones vinyl mode moves the key by
+    pub fn log_key_shift(&self) {
+        if self.tempo_mode != TempoMode::Vinyl {
+            return;
+        }
+        match self.tempo_filter().key_shift() {
+            (from, to) if from == to => {
+                log::info!("{}: key shifted {:+.2} semitones", self.id(), from)
+            }
+            (from, to) => log::info!(
+                "{}: key shifted {:+.2} to {:+.2} semitones",
+                self.id(),
+                from,
+                to
+            ),
+        }
+    }
+
+    /// seconds of the rendered slice
+    pub fn duration(&self) -> f32 {
+        self.tempo_filter().output_duration()
+    }
+
+    /// the source track seeked to the range
+    pub fn input(&self) -> Input {
+        Input {
+            path: self.a_path.clone(),
+            range: Some(self.range()),
+        }
+    }
+
+    /// adds filters of `input` seeked to the range to `graph`
+    pub fn add_filters(&self, graph: &mut FilterGraph, mut input: Pad) -> Pad {
+        if let Some((length, times)) = self.loop_span() {
+            let (begin, end) = self.range();
+            let samplerate = self.a.samplerate as f32;
+            input = graph.chain(
+                vec![input],
+                vec![
+                    Filter::new("aloop")
+                        .arg("loop", times)
+                        .arg("size", (length * samplerate).round())
+                        .arg("start", ((end - begin - length) * samplerate).round()),
+                    Filter::new("asetpts").arg("expr", "N/SR/TB"),
+                ],
+            );
+        }
+        let stretched = self.tempo_filter().apply(graph, input);
+        let loudnorm = self.loudnorm.then(|| Filter::new("loudnorm"));
+        graph.chain(
+            vec![stretched],
+            loudnorm
+                .into_iter()
+                .chain([Filter::new("volume").arg("volume", format!("{}dB", self.gain))])
+                .collect(),
+        )
+    }
+
+    pub fn filter_graph(&self) -> (FilterGraph, Pad) {
+        let mut graph = FilterGraph::new();
+        let out = self.add_filters(&mut graph, Pad::input(0));
+        (graph, out)
+    }
+
+    /// everything the rendered audio depends on besides the source file
+    pub fn definition(&self) -> String {
+        let (graph, graph_out) = self.filter_graph();
+        format!("slice {:?} {} {}", self.range(), graph, graph_out)
+    }
+
+    /// calls `progress` with seconds rendered
+    pub fn render(
+        &self,
+        out: &Path,
+        output_args: Vec<String>,
+        progress: impl FnMut(f32) -> Result<()>,
+    ) -> Result<()> {
+        let (from_hotcue, from_offset) = &self.from;
+        let (to_hotcue, to_offset) = &self.to;
+        log::debug!(
+            "{}: @{}+{} .. @{}+{}",
+            self.id(),
+            from_hotcue.hotcue,
+            from_offset,
+            to_hotcue.hotcue,
+            to_offset
         );
-        let filters = vec![a_scale.to_filters("0", "a"), vec![format!("[a] loudnorm")]].concat();
-        slice_cmd(&self.a_path, &filters, a_range, out)?;
-        Ok(())
+
+        let a_scale = self.tempo_filter();
+        log::debug!(
+            "{}: bpm={} target_bpm={} tempo={:?}",
+            self.id(),
+            self.a.bpm,
+            self.bpm,
+            a_scale
+        );
+        let (graph, graph_out) = self.filter_graph();
+        ffmpeg_complex_filter(
+            vec![self.input()],
+            out,
+            &graph,
+            &graph_out,
+            output_args,
+            progress,
+        )
+    }
+
+    pub fn execute(&self, out: &Path) -> Result<()> {
+        check_tempo_modes([self.tempo_mode].into_iter())?;
+        self.log_key_shift();
+        let mut bar = seconds_bar(self.id(), self.duration(), 0);
+        self.render(out, Encoding::default().args(out)?, |seconds| {
+            update_seconds(&mut bar, seconds);
+            Ok(())
+        })
     }
 }
@@ -0,0 +1,223 @@
+use super::{
+    crates::fetch_crate,
+    playlist::{fetch_playlist, print_tracks, write_mix_tracks, TrackModel},
+};
+use crate::mixxx::key::KeyNotation;
+use anyhow::Result;
+use rusqlite::Connection;
+use std::path::PathBuf;
+
+/// cost of one step on the Camelot wheel, in bpm
+const KEY_WEIGHT: f32 = 4.0;
+/// assumed key distance when a track has no key
+const UNKNOWN_KEY_DISTANCE: u8 = 2;
+/// cost of a transition exceeding `--max-bpm-change`
+const BPM_CAP_PENALTY: f32 = 1000.0;
+
+#[derive(Debug, clap::Parser)]
+pub struct SuggestOrderArgs {
+    #[arg(
+        long,
+        required_unless_present = "crate_id",
+        conflicts_with = "crate_id"
+    )]
+    playlist_id: Option<i32>,
+    #[arg(long)]
+    crate_id: Option<i32>,
+    /// track id to start the set with
+    #[arg(long)]
+    first: Option<i32>,
+    /// track id to end the set with
+    #[arg(long)]
+    last: Option<i32>,
+    /// max bpm change per transition
+    #[arg(long)]
+    max_bpm_change: Option<f32>,
+    #[arg(long)]
+    out: Option<PathBuf>,
+    #[arg(long, value_enum, default_value_t = KeyNotation::Lancelot)]
+    key_notation: KeyNotation,
+}
+
+fn key_distance(a: &TrackModel, b: &TrackModel) -> u8 {
+    match (a.key, b.key) {
+        (Some(a), Some(b)) => a.camelot_distance(b),
+        _ => UNKNOWN_KEY_DISTANCE,
+    }
+}
+
+fn transition_cost(a: &TrackModel, b: &TrackModel, max_bpm_change: Option<f32>) -> f32 {
+    let bpm_change = (a.bpm - b.bpm).abs();
+    let penalty = match max_bpm_change {
+        Some(max) if bpm_change > max => BPM_CAP_PENALTY,
+        _ => 0.0,
+    };
+    key_distance(a, b) as f32 * KEY_WEIGHT + bpm_change + penalty
+}
+
+fn path_cost(costs: &[Vec<f32>], path: &[usize]) -> f32 {
+    path.windows(2).map(|w| costs[w[0]][w[1]]).sum()
+}
+
+/// orders nodes by nearest neighbour from every allowed start, then improves with 2-opt.
+/// costs must be symmetric
+fn find_order(costs: &[Vec<f32>], first: Option<usize>, last: Option<usize>) -> Vec<usize> {
+    let n = costs.len();
+    if n < 2 {
+        return (0..n).collect();
+    }
+    let starts = match first {
+        Some(first) => vec![first],
+        None => (0..n).filter(|i| Some(*i) != last).collect(),
+    };
+    let mut best = starts
+        .into_iter()
+        .map(|start| {
+            let mut path = vec![start];
+            let mut rest = (0..n)
+                .filter(|i| *i != start && Some(*i) != last)
+                .collect::<Vec<_>>();
+            while !rest.is_empty() {
+                let current = *path.last().unwrap();
+                let (j, _) = rest
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| costs[current][**a].total_cmp(&costs[current][**b]))
+                    .unwrap();
+                path.push(rest.remove(j));
+            }
+            if let Some(last) = last {
+                path.push(last);
+            }
+            path
+        })
+        .min_by(|a, b| path_cost(costs, a).total_cmp(&path_cost(costs, b)))
+        .unwrap();
+
+    // reversing best[i..=j] only changes the edges around it
+    let lo = if first.is_some() { 1 } else { 0 };
+    let hi = if last.is_some() { n - 2 } else { n - 1 };
+    let edge = |path: &[usize], a: Option<usize>, b: usize| {
+        a.and_then(|a| path.get(b).map(|b| costs[path[a]][*b]))
+            .unwrap_or(0.0)
+    };
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for i in lo..hi {
+            for j in i + 1..=hi {
+                let before = edge(&best, i.checked_sub(1), i) + edge(&best, Some(j), j + 1);
+                let after = edge(&best, i.checked_sub(1), j) + edge(&best, Some(i), j + 1);
+                if after + 1e-4 < before {
+                    best[i..=j].reverse();
+                    improved = true;
+                }
+            }
+        }
+    }
+    best
+}
+
+pub fn suggest_order(conn: &Connection, args: &SuggestOrderArgs) -> Result<()> {
+    let mut playlist = match (args.playlist_id, args.crate_id) {
+        (Some(id), _) => fetch_playlist(conn, id)?,
+        (_, Some(id)) => fetch_crate(conn, id)?,
+        _ => anyhow::bail!("--playlist-id or --crate-id is required"),
+    };
+    let index_of = |track_id: Option<i32>| -> Result<Option<usize>> {
+        track_id
+            .map(|id| {
+                playlist
+                    .tracks
+                    .iter()
+                    .position(|track| track.track_id == id)
+                    .ok_or(anyhow::anyhow!("track id={} not in the list", id))
+            })
+            .transpose()
+    };
+    let (first, last) = (index_of(args.first)?, index_of(args.last)?);
+    if first.is_some() && first == last {
+        anyhow::bail!("first and last track must differ");
+    }
+
+    let costs = playlist
+        .tracks
+        .iter()
+        .map(|a| {
+            playlist
+                .tracks
+                .iter()
+                .map(|b| transition_cost(a, b, args.max_bpm_change))
+                .collect()
+        })
+        .collect::<Vec<Vec<_>>>();
+    let order = find_order(&costs, first, last);
+
+    let mut tracks = playlist.tracks.into_iter().map(Some).collect::<Vec<_>>();
+    playlist.tracks = order
+        .iter()
+        .enumerate()
+        .map(|(i, j)| {
+            let mut track = tracks[*j].take().unwrap();
+            track.position = i as i32 + 1;
+            track
+        })
+        .collect();
+    print_tracks(&playlist, args.key_notation);
+
+    let (known, unknown): (Vec<_>, Vec<_>) = playlist
+        .tracks
+        .windows(2)
+        .partition(|w| w[0].key.is_some() && w[1].key.is_some());
+    let key_clashes = known
+        .iter()
+        .filter(|w| key_distance(&w[0], &w[1]) > 1)
+        .count();
+    let max_bpm_change = playlist
+        .tracks
+        .windows(2)
+        .map(|w| (w[0].bpm - w[1].bpm).abs())
+        .fold(0.0, f32::max);
+    println!(
+        "key clashes={} unknown key={} max bpm change={:.1}",
+        key_clashes,
+        unknown.len(),
+        max_bpm_change
+    );
+    if let Some(max) = args.max_bpm_change {
+        if max_bpm_change > max {
+            log::warn!("no order keeps every bpm change within {}", max);
+        }
+    }
+
+    if let Some(out) = &args.out {
+        write_mix_tracks(playlist, out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_order, path_cost};
+
+    fn line_costs(positions: &[f32]) -> Vec<Vec<f32>> {
+        positions
+            .iter()
+            .map(|a| positions.iter().map(|b| (a - b).abs()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_find_order() {
+        let costs = line_costs(&[3.0, 0.0, 4.0, 1.0, 2.0]);
+        let order = find_order(&costs, None, None);
+        assert_eq!(path_cost(&costs, &order), 4.0);
+
+        let order = find_order(&costs, Some(4), None);
+        assert_eq!(order[0], 4);
+        assert_eq!(path_cost(&costs, &order), 6.0);
+
+        let order = find_order(&costs, Some(0), Some(3));
+        assert_eq!(order, vec![0, 2, 4, 1, 3]);
+    }
+}
@@ -0,0 +1,177 @@
+use anyhow::Result;
+use id3::{
+    frame::{Chapter, TableOfContents},
+    Frame, Tag, TagLike, Version,
+};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracklistEntry {
+    /// seconds in the rendered mix
+    pub start: f32,
+    pub track_id: i32,
+    pub title: String,
+    pub artist: Option<String>,
+}
+
+impl TracklistEntry {
+    fn display_title(&self) -> String {
+        match &self.artist {
+            Some(artist) if !artist.is_empty() => format!("{} - {}", artist, self.title),
+            _ => self.title.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tracklist {
+    pub entries: Vec<TracklistEntry>,
+    /// seconds of the rendered mix
+    pub duration: f32,
+}
+
+/// `mm:ss:ff` with 75 frames per second
+fn cue_sheet_time(seconds: f32) -> String {
+    let frames = (seconds.max(0.0) * 75.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        frames / 75 / 60,
+        frames / 75 % 60,
+        frames % 75
+    )
+}
+
+/// `mm:ss`, or `h:mm:ss` from an hour
+fn text_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{:02}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+impl Tracklist {
+    pub fn to_cue_sheet(&self, title: &str, file: &Path) -> String {
+        let file_type = match file.extension().and_then(|ext| ext.to_str()) {
+            Some("wav") => "WAVE".to_string(),
+            Some(ext) => ext.to_uppercase(),
+            None => "BINARY".to_string(),
+        };
+        let file_name = file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut lines = vec![
+            format!("TITLE {}", quote(title)),
+            format!("FILE {} {}", quote(&file_name), file_type),
+        ];
+        for (i, entry) in self.entries.iter().enumerate() {
+            lines.push(format!("  TRACK {:02} AUDIO", i + 1));
+            lines.push(format!("    TITLE {}", quote(&entry.title)));
+            if let Some(artist) = &entry.artist {
+                lines.push(format!("    PERFORMER {}", quote(artist)));
+            }
+            lines.push(format!("    INDEX 01 {}", cue_sheet_time(entry.start)));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn to_text(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| format!("{} {}\n", text_time(entry.start), entry.display_title()))
+            .collect()
+    }
+
+    /// replaces ID3 `CHAP`/`CTOC` frames of the mp3 with one chapter per track
+    pub fn write_chapters(&self, path: &Path) -> Result<()> {
+        let mut tag = Tag::read_from_path(path).unwrap_or_default();
+        tag.remove("CHAP");
+        tag.remove("CTOC");
+        let element_ids = (0..self.entries.len())
+            .map(|i| format!("chp{}", i))
+            .collect::<Vec<_>>();
+        for (i, entry) in self.entries.iter().enumerate() {
+            let end = self
+                .entries
+                .get(i + 1)
+                .map(|next| next.start)
+                .unwrap_or(self.duration);
+            tag.add_frame(Chapter {
+                element_id: element_ids[i].clone(),
+                start_time: (entry.start * 1000.0).round() as u32,
+                end_time: (end * 1000.0).round() as u32,
+                start_offset: u32::MAX,
+                end_offset: u32::MAX,
+                frames: vec![Frame::text("TIT2", entry.display_title())],
+            });
+        }
+        tag.add_frame(TableOfContents {
+            element_id: "toc".to_string(),
+            top_level: true,
+            ordered: true,
+            elements: element_ids,
+            frames: vec![],
+        });
+        tag.write_to_path(path, Version::Id3v24)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tracklist, TracklistEntry};
+    use std::path::Path;
+
+    #[test]
+    fn test_tracklist() {
+        let entry = |start, title: &str, artist: Option<&str>| TracklistEntry {
+            start,
+            track_id: 0,
+            title: title.to_string(),
+            artist: artist.map(|a| a.to_string()),
+        };
+        let tracklist = Tracklist {
+            entries: vec![
+                entry(0.0, "Alpha", Some("A")),
+                entry(65.5, "Bravo", None),
+                entry(3725.0, "Charlie", Some("C")),
+            ],
+            duration: 4000.0,
+        };
+        assert_eq!(
+            tracklist.to_text(),
+            "00:00 A - Alpha\n01:06 Bravo\n1:02:05 C - Charlie\n"
+        );
+        assert_eq!(
+            tracklist.to_cue_sheet("mix", Path::new("out/mix.mp3")),
+            [
+                "TITLE \"mix\"",
+                "FILE \"mix.mp3\" MP3",
+                "  TRACK 01 AUDIO",
+                "    TITLE \"Alpha\"",
+                "    PERFORMER \"A\"",
+                "    INDEX 01 00:00:00",
+                "  TRACK 02 AUDIO",
+                "    TITLE \"Bravo\"",
+                "    INDEX 01 01:05:38",
+                "  TRACK 03 AUDIO",
+                "    TITLE \"Charlie\"",
+                "    PERFORMER \"C\"",
+                "    INDEX 01 62:05:00",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{
+    export::{traktor, ExportPlaylist},
+    mixxx::{
+        cue::{Cue, CueType},
+        library::Library,
+        repo::AsRepo,
+        track_location::TrackLocation,
+    },
+};
+use anyhow::Result;
+use rusqlite::Connection;
+use std::{collections::HashMap, path::PathBuf};
+
+#[derive(Debug, clap::Parser)]
+pub struct TraktorArgs {
+    #[command(subcommand)]
+    command: TraktorCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum TraktorCommand {
+    /// export playlists and crates as NML
+    Export(ExportNmlArgs),
+    /// create or update cues of tracks in an NML
+    Import(ImportNmlArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ExportNmlArgs {
+    #[arg(long)]
+    playlist_id: Vec<i32>,
+    #[arg(long)]
+    crate_id: Vec<i32>,
+    /// traktor volume name for paths without a windows drive, e.g. "Macintosh HD"
+    #[arg(long, default_value = "")]
+    volume: String,
+    #[arg(long)]
+    out: PathBuf,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ImportNmlArgs {
+    input: PathBuf,
+    /// traktor volume name of the boot disk, other volumes are under /Volumes
+    #[arg(long, default_value = "")]
+    volume: String,
+}
+
+pub fn export_nml(conn: &Connection, args: &ExportNmlArgs) -> Result<()> {
+    if args.playlist_id.is_empty() && args.crate_id.is_empty() {
+        anyhow::bail!("--playlist-id or --crate-id is required");
+    }
+    let playlists = args
+        .playlist_id
+        .iter()
+        .map(|id| ExportPlaylist::fetch(conn, *id))
+        .chain(
+            args.crate_id
+                .iter()
+                .map(|id| ExportPlaylist::fetch_crate(conn, *id)),
+        )
+        .collect::<Result<Vec<_>>>()?;
+    std::fs::write(&args.out, traktor::render(&playlists, &args.volume)?)?;
+    log::debug!(
+        "{} playlists exported to {}",
+        playlists.len(),
+        args.out.display()
+    );
+    Ok(())
+}
+
+/// hot cues and loops are matched by number, other types are one per track
+fn find_existing<'c>(cues: &'c [Cue], r#type: &CueType, hotcue: i32) -> Option<&'c Cue> {
+    cues.iter().find(|cue| {
+        cue.r#type == *r#type
+            && match r#type {
+                CueType::HotCue | CueType::Loop => cue.hotcue == hotcue,
+                _ => true,
+            }
+    })
+}
+
+pub fn import_nml(conn: &Connection, args: &ImportNmlArgs) -> Result<()> {
+    let entries = traktor::parse(&std::fs::read_to_string(&args.input)?, &args.volume)?;
+
+    let tx = conn.unchecked_transaction()?;
+    let lib_repo = Library::repo(&tx);
+    let cue_repo = Cue::repo(&tx);
+    let location_ids = TrackLocation::repo(&tx)
+        .select_all()?
+        .into_iter()
+        .map(|location| (location.location, location.id))
+        .collect::<HashMap<_, _>>();
+    let libraries = lib_repo
+        .select_all()?
+        .into_iter()
+        .map(|library| (library.location, library))
+        .collect::<HashMap<_, _>>();
+
+    let (mut inserted, mut updated) = (0, 0);
+    for entry in entries.iter() {
+        let Some(library) = location_ids
+            .get(&entry.location)
+            .and_then(|id| libraries.get(id))
+        else {
+            log::warn!("{} not in the library", entry.location.display());
+            continue;
+        };
+        let samples = |ms: f32| ms / 1000.0 * library.samplerate as f32 * 2.0;
+        let existing = cue_repo.find_by_track_id(library.id)?;
+        for nml_cue in entry.cues.iter() {
+            let Some(r#type) = nml_cue.cue_type() else {
+                continue;
+            };
+            let hotcue = match r#type {
+                CueType::HotCue | CueType::Loop => nml_cue.hotcue,
+                _ => -1,
+            };
+            let cue = Cue {
+                id: 0,
+                track_id: library.id,
+                r#type,
+                position: samples(nml_cue.start),
+                length: samples(nml_cue.len),
+                hotcue,
+            };
+            match find_existing(&existing, &cue.r#type, hotcue) {
+                Some(old) => {
+                    cue_repo.update(&Cue { id: old.id, ..cue })?;
+                    updated += 1;
+                }
+                None => {
+                    cue_repo.insert(&cue)?;
+                    inserted += 1;
+                }
+            }
+        }
+    }
+    tx.commit()?;
+    log::debug!("{} cues inserted, {} cues updated", inserted, updated);
+    Ok(())
+}
+
+pub fn traktor_command(conn: &Connection, args: &TraktorArgs) -> Result<()> {
+    match &args.command {
+        TraktorCommand::Export(args) => export_nml(conn, args),
+        TraktorCommand::Import(args) => import_nml(conn, args),
+    }
+}
@@ -0,0 +1,230 @@
+use super::mix_plan::{EqEnvelope, FadeCurve, ThreeBandEq};
+use crate::ffmpeg::filter_graph::{send_commands, Filter, FilterGraph, Pad};
+use serde::{Deserialize, Serialize};
+
+/// steps of a filter sweep
+const SWEEP_STEPS: usize = 16;
+/// cutoff of the bass swap, Hz
+const BASS_SWAP_FREQUENCY: f32 = 200.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransitionKind {
+    /// fades with the transition's `curve` and eqs
+    #[default]
+    Fade,
+    Linear,
+    EqualPower,
+    Log,
+    /// both decks play, the lows swap from the outgoing to the incoming one halfway
+    BassSwap,
+    /// high-pass rising on the outgoing deck, low-pass opening on the incoming one
+    FilterSweep,
+    /// the outgoing deck stops after a beat and echoes
+    EchoOut,
+    /// the incoming deck only, from the downbeat
+    Cut,
+}
+
+impl TransitionKind {
+    /// applies the transition's eqs
+    fn fades(self) -> bool {
+        matches!(
+            self,
+            Self::Fade | Self::Linear | Self::EqualPower | Self::Log
+        )
+    }
+}
+
+/// mixes two decks trimmed and stretched to the mix tempo
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub kind: TransitionKind,
+    pub curve: FadeCurve,
+    pub out_eq: ThreeBandEq,
+    pub in_eq: ThreeBandEq,
+    /// override the eqs' bands they have keyframes of, with any kind
+    pub out_envelope: EqEnvelope,
+    pub in_envelope: EqEnvelope,
+    /// seconds
+    pub duration: f32,
+    /// seconds of a beat at the mix tempo
+    pub beat: f32,
+}
+
+fn afade(fade: &str, start: f32, duration: f32, curve: FadeCurve) -> Filter {
+    Filter::new("afade")
+        .arg("t", fade)
+        .arg("st", start)
+        .arg("duration", duration)
+        .arg("curve", curve.as_str())
+}
+
+/// sums both decks
+fn mix(graph: &mut FilterGraph, a: Pad, b: Pad) -> Pad {
+    graph.chain(
+        vec![a, b],
+        vec![Filter::new("amix")
+            .arg("duration", "longest")
+            .arg("normalize", 0)],
+    )
+}
+
+impl Transition {
+    /// exponential steps from `from` to `to` over the transition
+    fn sweep(&self, from: f32, to: f32) -> Vec<(f32, f32)> {
+        (0..=SWEEP_STEPS)
+            .map(|i| {
+                let t = i as f32 / SWEEP_STEPS as f32;
+                (self.duration * t, (from * (to / from).powf(t)).round())
+            })
+            .collect()
+    }
+
+    fn fade(&self, graph: &mut FilterGraph, a: Pad, b: Pad, curve: FadeCurve) -> Pad {
+        let out_eq = self.out_eq.automate(&self.out_envelope, self.beat, graph);
+        let a = graph.chain(
+            vec![a],
+            [vec![afade("out", 0.0, self.duration, curve)], out_eq].concat(),
+        );
+        let in_eq = self.in_eq.automate(&self.in_envelope, self.beat, graph);
+        let b = graph.chain(
+            vec![b],
+            [vec![afade("in", 0.0, self.duration, curve)], in_eq].concat(),
+        );
+        mix(graph, a, b)
+    }
+
+    /// adds the filters mixing `a` into `b` to `graph`
+    pub fn add_filters(&self, graph: &mut FilterGraph, mut a: Pad, mut b: Pad) -> Pad {
+        if !self.kind.fades() {
+            let out_eq = ThreeBandEq::default().automate(&self.out_envelope, self.beat, graph);
+            a = graph.chain(vec![a], out_eq);
+            let in_eq = ThreeBandEq::default().automate(&self.in_envelope, self.beat, graph);
+            b = graph.chain(vec![b], in_eq);
+        }
+        match self.kind {
+            TransitionKind::Fade => self.fade(graph, a, b, self.curve),
+            TransitionKind::Linear => self.fade(graph, a, b, FadeCurve::Tri),
+            TransitionKind::EqualPower => self.fade(graph, a, b, FadeCurve::Qsin),
+            TransitionKind::Log => self.fade(graph, a, b, FadeCurve::Log),
+            TransitionKind::BassSwap => {
+                // on the beat nearest to the middle
+                let swap = (self.duration / self.beat / 2.0).round() * self.beat;
+                let rest = self.duration - swap;
+                let a = graph.chain(
+                    vec![a],
+                    vec![
+                        afade("out", swap, rest, FadeCurve::Tri),
+                        Filter::new("highpass")
+                            .arg("f", BASS_SWAP_FREQUENCY)
+                            .arg("enable", format!("gte(t,{})", swap)),
+                    ],
+                );
+                let b = graph.chain(
+                    vec![b],
+                    vec![
+                        afade("in", 0.0, swap, FadeCurve::Tri),
+                        Filter::new("highpass")
+                            .arg("f", BASS_SWAP_FREQUENCY)
+                            .arg("enable", format!("lt(t,{})", swap)),
+                    ],
+                );
+                mix(graph, a, b)
+            }
+            TransitionKind::FilterSweep => {
+                let highpass = graph.instance(Filter::new("highpass").arg("f", 20));
+                let a = graph.chain(
+                    vec![a],
+                    vec![
+                        send_commands(highpass.name(), "f", &self.sweep(20.0, 5000.0)),
+                        highpass,
+                        afade("out", 0.0, self.duration, FadeCurve::Tri),
+                    ],
+                );
+                let lowpass = graph.instance(Filter::new("lowpass").arg("f", 200));
+                let b = graph.chain(
+                    vec![b],
+                    vec![
+                        send_commands(lowpass.name(), "f", &self.sweep(200.0, 20000.0)),
+                        lowpass,
+                        afade("in", 0.0, self.duration, FadeCurve::Tri),
+                    ],
+                );
+                mix(graph, a, b)
+            }
+            TransitionKind::EchoOut => {
+                let delays = (1..=4)
+                    .map(|i| format!("{}", (self.beat * 1000.0 * i as f32).round()))
+                    .collect::<Vec<_>>();
+                let decays = (1..=4)
+                    .map(|i| format!("{:.3}", 0.6f32.powi(i)))
+                    .collect::<Vec<_>>();
+                let a = graph.chain(
+                    vec![a],
+                    vec![
+                        afade("out", 0.0, self.beat, FadeCurve::Exp),
+                        Filter::new("aecho")
+                            .arg("in_gain", 1)
+                            .arg("out_gain", 0.8)
+                            .arg("delays", delays.join("|"))
+                            .arg("decays", decays.join("|")),
+                    ],
+                );
+                let b = graph.chain(vec![b], vec![afade("in", 0.0, self.beat, FadeCurve::Tri)]);
+                let out = mix(graph, a, b);
+                // the echo rings on past the outgoing deck
+                graph.chain(
+                    vec![out],
+                    vec![Filter::new("atrim").arg("duration", self.duration)],
+                )
+            }
+            TransitionKind::Cut => {
+                graph.add(vec![a], vec![Filter::new("anullsink")], 0);
+                b
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Transition, TransitionKind};
+    use crate::{
+        cmds::mix_plan::{EqEnvelope, FadeCurve, ThreeBandEq},
+        ffmpeg::filter_graph::{FilterGraph, Pad},
+    };
+
+    fn render(kind: TransitionKind) -> String {
+        let transition = Transition {
+            kind,
+            curve: FadeCurve::Squ,
+            out_eq: ThreeBandEq::default(),
+            in_eq: ThreeBandEq::default(),
+            out_envelope: EqEnvelope::default(),
+            in_envelope: EqEnvelope::default(),
+            duration: 15.0,
+            beat: 0.5,
+        };
+        let mut graph = FilterGraph::new();
+        let out = transition.add_filters(&mut graph, Pad::input(0), Pad::input(1));
+        format!("{} {}", graph, out)
+    }
+
+    #[test]
+    fn test_transition() {
+        assert_eq!(render(TransitionKind::Cut), "[0:a]anullsink [1:a]");
+        assert_eq!(
+            render(TransitionKind::BassSwap),
+            [
+                r"[0:a]afade=t=out:st=7.5:duration=7.5:curve=tri,highpass=f=200:enable=gte(t\,7.5)[p0]",
+                r"[1:a]afade=t=in:st=0:duration=7.5:curve=tri,highpass=f=200:enable=lt(t\,7.5)[p1]",
+                "[p0][p1]amix=duration=longest:normalize=0[p2] [p2]",
+            ]
+            .join(";")
+        );
+        assert!(render(TransitionKind::EqualPower).contains("curve=qsin"));
+        assert!(render(TransitionKind::EchoOut)
+            .ends_with("amix=duration=longest:normalize=0[p2];[p2]atrim=duration=15[p3] [p3]"));
+    }
+}
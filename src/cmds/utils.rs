@@ -1,4 +1,9 @@
-use crate::mixxx::{cue::Cue, library::Library, repo::AsRepo, track_location::TrackLocation};
+use crate::{
+    ffmpeg::probe::StreamInfo,
+    mixxx::{
+        beats::BeatGrid, cue::Cue, library::Library, repo::AsRepo, track_location::TrackLocation,
+    },
+};
 use anyhow::Result;
 use rusqlite::Connection;
 use std::path::PathBuf;
@@ -10,7 +15,7 @@ pub fn get_track(conn: &Connection, track_id: i32) -> Result<(PathBuf, Library)>
         .select(track_id)?
         .ok_or(anyhow::anyhow!("track not found"))?;
     let track_location = track_location_repo
-        .select(track_id)?
+        .select(library.location)?
         .ok_or(anyhow::anyhow!("track location not found"))?
         .location;
     Ok((track_location, library))
@@ -18,18 +23,67 @@ pub fn get_track(conn: &Connection, track_id: i32) -> Result<(PathBuf, Library)>
 
 pub fn get_hotcue(conn: &Connection, track_id: i32, hotcue: u8) -> Result<Cue> {
     let cue_repo = Cue::repo(conn);
-    let cues = cue_repo
-        .hot_cues_by_track_id(track_id)?
-        .into_iter()
-        .collect::<Vec<_>>();
-    let cue = cues
-        .into_iter()
-        .find(|cue| cue.hotcue == hotcue)
-        .ok_or(anyhow::anyhow!("hotcue not found"))?;
-    Ok(cue)
+    cue_repo
+        .hot_cue_by_track_id(track_id, hotcue)?
+        .ok_or(anyhow::anyhow!("hotcue not found"))
+}
+
+/// seconds a file may differ from `library.duration`
+const DURATION_TOLERANCE: f32 = 1.0;
+
+/// differences between a library row and its file, which misplace cues
+pub fn check_track(library: &Library, info: &StreamInfo) -> Vec<String> {
+    let mut problems = vec![];
+    if info.sample_rate != library.samplerate {
+        problems.push(format!(
+            "sample rate is {} Hz, analyzed at {} Hz",
+            info.sample_rate, library.samplerate
+        ));
+    }
+    if info.channels != 2 {
+        problems.push(format!(
+            "{} channel(s), cue positions are read as stereo samples",
+            info.channels
+        ));
+    }
+    if library.channels > 0 && info.channels != library.channels as u32 {
+        problems.push(format!(
+            "{} channel(s), analyzed with {}",
+            info.channels, library.channels
+        ));
+    }
+    if (info.duration - library.duration).abs() > DURATION_TOLERANCE {
+        problems.push(format!(
+            "duration is {:.1}s, library has {:.1}s",
+            info.duration, library.duration
+        ));
+    }
+    problems
 }
 
 /// returns seconds at cue in original bpm
 pub fn cue_at(library: &Library, cue: &Cue) -> f32 {
-    cue.position / library.samplerate as f32 / 2.0
+    cue.seconds(library.samplerate)
+}
+
+/// beats a hot cue may be off its downbeat without a warning
+const DOWNBEAT_TOLERANCE: f32 = 0.25;
+
+/// seconds of the downbeat nearest to cue, where segments are cut and aligned
+pub fn cue_downbeat(library: &Library, grid: &BeatGrid, cue: &Cue) -> f32 {
+    grid.nearest_downbeat(cue_at(library, cue))
+}
+
+/// warns when cue is moved far to its downbeat
+pub fn check_downbeat(library: &Library, grid: &BeatGrid, cue: &Cue) {
+    let seconds = cue_at(library, cue);
+    let off = grid.beat_at(seconds) - grid.beat_at(grid.nearest_downbeat(seconds));
+    if off.abs() > DOWNBEAT_TOLERANCE {
+        log::warn!(
+            "track id={} hotcue {} is {:.2} beats off its nearest downbeat, aligned to it",
+            library.id,
+            cue.hotcue,
+            off
+        );
+    }
 }
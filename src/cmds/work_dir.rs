@@ -0,0 +1,72 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// directory of intermediate files of one render, cleaned up on drop unless kept
+#[derive(Debug)]
+pub struct WorkDir {
+    path: PathBuf,
+    /// created by us, removed as a whole
+    temporary: bool,
+    files: Vec<PathBuf>,
+    keep: bool,
+}
+
+impl WorkDir {
+    /// `path` or a new temporary directory
+    pub fn new(path: Option<&Path>, keep: bool) -> Result<Self> {
+        let (path, temporary) = match path {
+            Some(path) => (path.to_path_buf(), false),
+            None => (
+                std::env::temp_dir().join(format!("mixxx_cli-{}", std::process::id())),
+                true,
+            ),
+        };
+        std::fs::create_dir_all(&path)?;
+        Ok(Self {
+            path,
+            temporary,
+            files: vec![],
+            keep,
+        })
+    }
+
+    /// path of an intermediate file named `name`
+    pub fn file(&mut self, name: &str) -> PathBuf {
+        let path = self.path.join(name);
+        self.files.push(path.clone());
+        path
+    }
+
+    /// an intermediate directory named `name`, removed with its content
+    pub fn dir(&mut self, name: &str) -> Result<PathBuf> {
+        let path = self.file(name);
+        std::fs::create_dir_all(&path)?;
+        Ok(path)
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if self.keep {
+            log::info!("intermediates kept in {}", self.path.display());
+            return;
+        }
+        let result = if self.temporary {
+            std::fs::remove_dir_all(&self.path)
+        } else {
+            self.files
+                .iter()
+                .filter(|file| file.exists())
+                .try_for_each(|file| {
+                    if file.is_dir() {
+                        std::fs::remove_dir_all(file)
+                    } else {
+                        std::fs::remove_file(file)
+                    }
+                })
+        };
+        if let Err(e) = result {
+            log::warn!("failed to clean up {}: {}", self.path.display(), e);
+        }
+    }
+}
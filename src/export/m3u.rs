@@ -0,0 +1,19 @@
+use super::{export_path, ExportPlaylist};
+use std::path::Path;
+
+/// extended M3U in UTF-8
+pub fn render(playlist: &ExportPlaylist, base_dir: Option<&Path>) -> String {
+    let mut lines = vec![
+        "#EXTM3U".to_string(),
+        format!("#PLAYLIST:{}", playlist.name),
+    ];
+    for track in playlist.tracks.iter() {
+        lines.push(format!(
+            "#EXTINF:{},{}",
+            track.library.duration.round() as i64,
+            track.display_title()
+        ));
+        lines.push(export_path(&track.path, base_dir).display().to_string());
+    }
+    lines.join("\n") + "\n"
+}
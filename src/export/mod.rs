@@ -0,0 +1,198 @@
+use crate::mixxx::{
+    crate_track::CrateTrack, crates::Crate, cue::Cue, library::Library, playlist::Playlist,
+    playlist_track::PlaylistTrack, repo::AsRepo, track_location::TrackLocation,
+};
+use anyhow::Result;
+use rusqlite::Connection;
+use std::path::{Component, Path, PathBuf};
+
+pub mod m3u;
+pub mod pls;
+pub mod rekordbox;
+pub mod traktor;
+pub mod xspf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum PlaylistFormat {
+    M3u8,
+    Pls,
+    Xspf,
+}
+
+#[derive(Debug)]
+pub struct ExportTrack {
+    pub path: PathBuf,
+    pub library: Library,
+    /// all types, ordered by position
+    pub cues: Vec<Cue>,
+}
+
+impl ExportTrack {
+    pub fn fetch(conn: &Connection, track_id: i32) -> Result<Self> {
+        let library = Library::repo(conn)
+            .select(track_id)?
+            .ok_or(anyhow::anyhow!("track id={} not found", track_id))?;
+        let path = TrackLocation::repo(conn)
+            .select(library.location)?
+            .ok_or(anyhow::anyhow!(
+                "track location of id={} not found",
+                track_id
+            ))?
+            .location;
+        let cues = Cue::repo(conn).find_by_track_id(track_id)?;
+        Ok(Self {
+            path,
+            library,
+            cues,
+        })
+    }
+
+    /// `artist - title`, or title only
+    pub fn display_title(&self) -> String {
+        match &self.library.artist {
+            Some(artist) if !artist.is_empty() => format!("{} - {}", artist, self.library.title),
+            _ => self.library.title.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportPlaylist {
+    pub name: String,
+    pub tracks: Vec<ExportTrack>,
+}
+
+impl ExportPlaylist {
+    pub fn fetch(conn: &Connection, id: i32) -> Result<Self> {
+        let playlist = Playlist::repo(conn)
+            .select(id)?
+            .ok_or(anyhow::anyhow!("playlist id={} not found", id))?;
+        let tracks = PlaylistTrack::repo(conn)
+            .find_by_playlist_id(id)?
+            .iter()
+            .map(|playlist_track| ExportTrack::fetch(conn, playlist_track.track_id))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            name: playlist.name,
+            tracks,
+        })
+    }
+
+    pub fn fetch_crate(conn: &Connection, id: i32) -> Result<Self> {
+        let c = Crate::repo(conn)
+            .select(id)?
+            .ok_or(anyhow::anyhow!("crate id={} not found", id))?;
+        let tracks = CrateTrack::repo(conn)
+            .find_by_crate_id(id)?
+            .iter()
+            .map(|crate_track| ExportTrack::fetch(conn, crate_track.track_id))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            name: c.name,
+            tracks,
+        })
+    }
+
+    pub fn render(&self, format: PlaylistFormat, base_dir: Option<&Path>) -> Result<String> {
+        match format {
+            PlaylistFormat::M3u8 => Ok(m3u::render(self, base_dir)),
+            PlaylistFormat::Pls => Ok(pls::render(self, base_dir)),
+            PlaylistFormat::Xspf => xspf::render(self, base_dir),
+        }
+    }
+}
+
+/// percent-encodes a path for URIs, keeping `/`
+pub fn percent_encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// `file://{host}/path` of an absolute path, windows drives included.
+/// relative paths are only percent-encoded
+pub fn file_uri(path: &Path, host: &str) -> String {
+    let path = path.display().to_string().replace('\\', "/");
+    match path.strip_prefix('/') {
+        Some(path) => format!("file://{}/{}", host, percent_encode(path)),
+        // `C:/...`, keep the colon of the drive
+        None if path.as_bytes().first().is_some_and(u8::is_ascii_alphabetic)
+            && path.as_bytes().get(1) == Some(&b':') =>
+        {
+            format!(
+                "file://{}/{}:{}",
+                host,
+                &path[..1],
+                percent_encode(&path[2..])
+            )
+        }
+        None => percent_encode(&path),
+    }
+}
+
+/// path relative to `base_dir`, or as is
+pub fn export_path(path: &Path, base_dir: Option<&Path>) -> PathBuf {
+    match base_dir {
+        Some(base_dir) => relative_path(path, base_dir),
+        None => path.to_path_buf(),
+    }
+}
+
+fn relative_path(path: &Path, base_dir: &Path) -> PathBuf {
+    let path = path.components().collect::<Vec<_>>();
+    let base_dir = base_dir
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect::<Vec<_>>();
+    let common = path
+        .iter()
+        .zip(base_dir.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    base_dir[common..]
+        .iter()
+        .map(|_| Component::ParentDir)
+        .chain(path[common..].iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_uri, relative_path};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_relative_path() {
+        let path = Path::new("/music/house/a.mp3");
+        assert_eq!(
+            relative_path(path, Path::new("/music")),
+            PathBuf::from("house/a.mp3")
+        );
+        assert_eq!(
+            relative_path(path, Path::new("/music/techno/")),
+            PathBuf::from("../house/a.mp3")
+        );
+        assert_eq!(
+            relative_path(Path::new("F:/house/a.mp3"), Path::new("F:/")),
+            PathBuf::from("house/a.mp3")
+        );
+    }
+
+    #[test]
+    fn test_file_uri() {
+        assert_eq!(
+            file_uri(Path::new("/music/a b#.mp3"), ""),
+            "file:///music/a%20b%23.mp3"
+        );
+        assert_eq!(
+            file_uri(Path::new("F:/music/a.mp3"), "localhost"),
+            "file://localhost/F:/music/a.mp3"
+        );
+        assert_eq!(file_uri(Path::new("é:a.mp3"), ""), "%C3%A9%3Aa.mp3");
+    }
+}
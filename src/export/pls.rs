@@ -0,0 +1,23 @@
+use super::{export_path, ExportPlaylist};
+use std::path::Path;
+
+pub fn render(playlist: &ExportPlaylist, base_dir: Option<&Path>) -> String {
+    let mut lines = vec!["[playlist]".to_string()];
+    for (i, track) in playlist.tracks.iter().enumerate() {
+        let n = i + 1;
+        lines.push(format!(
+            "File{}={}",
+            n,
+            export_path(&track.path, base_dir).display()
+        ));
+        lines.push(format!("Title{}={}", n, track.display_title()));
+        lines.push(format!(
+            "Length{}={}",
+            n,
+            track.library.duration.round() as i64
+        ));
+    }
+    lines.push(format!("NumberOfEntries={}", playlist.tracks.len()));
+    lines.push("Version=2".to_string());
+    lines.join("\n") + "\n"
+}
@@ -0,0 +1,237 @@
+use super::{file_uri, ExportPlaylist, ExportTrack};
+use crate::mixxx::{
+    beats::BeatGrid,
+    cue::{Cue, CueType},
+    key::{Key, KeyNotation},
+};
+use anyhow::Result;
+use quick_xml::{
+    events::{BytesDecl, Event},
+    Writer,
+};
+use std::collections::BTreeMap;
+
+/// rekordbox has hot cues A..H
+const HOT_CUES: i32 = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionMark {
+    /// 0: cue, 4: loop
+    pub r#type: u8,
+    pub start: f32,
+    pub end: Option<f32>,
+    /// hot cue number, -1 for memory cues
+    pub num: i32,
+}
+
+/// hot cues, the main cue as a memory cue and loops
+pub fn position_marks(cues: &[Cue], samplerate: usize) -> Vec<PositionMark> {
+    let hot_cue_num = |cue: &Cue| {
+        if (0..HOT_CUES).contains(&cue.hotcue) {
+            cue.hotcue
+        } else {
+            -1
+        }
+    };
+    cues.iter()
+        .filter_map(|cue| {
+            let start = cue.seconds(samplerate);
+            match cue.r#type {
+                CueType::HotCue if hot_cue_num(cue) >= 0 => Some(PositionMark {
+                    r#type: 0,
+                    start,
+                    end: None,
+                    num: cue.hotcue,
+                }),
+                CueType::MainCue => Some(PositionMark {
+                    r#type: 0,
+                    start,
+                    end: None,
+                    num: -1,
+                }),
+                CueType::Loop if cue.length > 0.0 => Some(PositionMark {
+                    r#type: 4,
+                    start,
+                    end: Some(start + cue.length_seconds(samplerate)),
+                    num: hot_cue_num(cue),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn write_track(w: &mut Writer<Vec<u8>>, track: &ExportTrack) -> Result<()> {
+    let library = &track.library;
+    let kind = track
+        .path
+        .extension()
+        .map(|ext| format!("{} File", ext.to_string_lossy().to_uppercase()))
+        .unwrap_or_default();
+    let tonality = Key::from_library(library)?
+        .map(|key| key.to_notation(KeyNotation::Traditional))
+        .unwrap_or_default();
+    let grid = BeatGrid::from_library(library)?;
+    let id = library.id.to_string();
+    let total_time = (library.duration.round() as i64).to_string();
+    let bpm = format!("{:.2}", library.bpm);
+    let bitrate = (library.bitrate as i64).to_string();
+    let samplerate = library.samplerate.to_string();
+    // 0..5 stars to 0..255
+    let rating = (library.rating.clamp(0, 5) * 51).to_string();
+    let location = file_uri(&track.path, "localhost");
+    let attrs = [
+        ("TrackID", id.as_str()),
+        ("Name", library.title.as_str()),
+        ("Artist", library.artist.as_deref().unwrap_or_default()),
+        ("Album", library.album.as_deref().unwrap_or_default()),
+        ("Genre", library.genre.as_deref().unwrap_or_default()),
+        ("Kind", kind.as_str()),
+        ("TotalTime", total_time.as_str()),
+        (
+            "TrackNumber",
+            library.tracknumber.as_deref().unwrap_or_default(),
+        ),
+        ("Year", library.year.as_deref().unwrap_or_default()),
+        ("AverageBpm", bpm.as_str()),
+        ("BitRate", bitrate.as_str()),
+        ("SampleRate", samplerate.as_str()),
+        ("Comments", library.comment.as_deref().unwrap_or_default()),
+        ("Rating", rating.as_str()),
+        ("Location", location.as_str()),
+        ("Tonality", tonality.as_str()),
+    ];
+    w.create_element("TRACK")
+        .with_attributes(attrs)
+        .write_inner_content(|w| -> Result<()> {
+            for marker in grid.tempo_markers() {
+                w.create_element("TEMPO")
+                    .with_attribute(("Inizio", format!("{:.3}", marker.seconds).as_str()))
+                    .with_attribute(("Bpm", format!("{:.2}", marker.bpm).as_str()))
+                    .with_attribute(("Metro", "4/4"))
+                    .with_attribute((
+                        "Battito",
+                        (marker.beat.rem_euclid(4) + 1).to_string().as_str(),
+                    ))
+                    .write_empty()?;
+            }
+            for mark in position_marks(&track.cues, library.samplerate) {
+                let mut element = w
+                    .create_element("POSITION_MARK")
+                    .with_attribute(("Name", ""))
+                    .with_attribute(("Type", mark.r#type.to_string().as_str()))
+                    .with_attribute(("Start", format!("{:.3}", mark.start).as_str()));
+                if let Some(end) = mark.end {
+                    element = element.with_attribute(("End", format!("{:.3}", end).as_str()));
+                }
+                element
+                    .with_attribute(("Num", mark.num.to_string().as_str()))
+                    .write_empty()?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+/// rekordbox `DJ_PLAYLISTS` XML with a collection of every track in the playlists
+pub fn render(playlists: &[ExportPlaylist]) -> Result<String> {
+    let collection = playlists
+        .iter()
+        .flat_map(|playlist| playlist.tracks.iter())
+        .map(|track| (track.library.id, track))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut writer = Writer::new_with_indent(vec![], b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("DJ_PLAYLISTS")
+        .with_attribute(("Version", "1.0.0"))
+        .write_inner_content(|w| -> Result<()> {
+            w.create_element("PRODUCT")
+                .with_attribute(("Name", env!("CARGO_PKG_NAME")))
+                .with_attribute(("Version", env!("CARGO_PKG_VERSION")))
+                .with_attribute(("Company", ""))
+                .write_empty()?;
+            w.create_element("COLLECTION")
+                .with_attribute(("Entries", collection.len().to_string().as_str()))
+                .write_inner_content(|w| -> Result<()> {
+                    for track in collection.values() {
+                        write_track(w, track)?;
+                    }
+                    Ok(())
+                })?;
+            w.create_element("PLAYLISTS")
+                .write_inner_content(|w| -> Result<()> {
+                    w.create_element("NODE")
+                        .with_attribute(("Type", "0"))
+                        .with_attribute(("Name", "ROOT"))
+                        .with_attribute(("Count", playlists.len().to_string().as_str()))
+                        .write_inner_content(|w| -> Result<()> {
+                            for playlist in playlists.iter() {
+                                w.create_element("NODE")
+                                    .with_attribute(("Name", playlist.name.as_str()))
+                                    .with_attribute(("Type", "1"))
+                                    .with_attribute(("KeyType", "0"))
+                                    .with_attribute((
+                                        "Entries",
+                                        playlist.tracks.len().to_string().as_str(),
+                                    ))
+                                    .write_inner_content(|w| -> Result<()> {
+                                        for track in playlist.tracks.iter() {
+                                            w.create_element("TRACK")
+                                                .with_attribute((
+                                                    "Key",
+                                                    track.library.id.to_string().as_str(),
+                                                ))
+                                                .write_empty()?;
+                                        }
+                                        Ok(())
+                                    })?;
+                            }
+                            Ok(())
+                        })?;
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(String::from_utf8(writer.into_inner())? + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{position_marks, PositionMark};
+    use crate::mixxx::cue::{Cue, CueType};
+
+    #[test]
+    fn test_position_marks() {
+        let cue = |r#type, position, length, hotcue| Cue {
+            id: 0,
+            track_id: 1,
+            r#type,
+            position,
+            length,
+            hotcue,
+        };
+        let cues = vec![
+            cue(CueType::MainCue, 200.0, 0.0, -1),
+            cue(CueType::HotCue, 400.0, 0.0, 2),
+            cue(CueType::HotCue, 600.0, 0.0, 9),
+            cue(CueType::Loop, 800.0, 400.0, 3),
+            cue(CueType::Intro, 1000.0, 0.0, -1),
+        ];
+        let mark = |r#type, start, end, num| PositionMark {
+            r#type,
+            start,
+            end,
+            num,
+        };
+        assert_eq!(
+            position_marks(&cues, 100),
+            vec![
+                mark(0, 1.0, None, -1),
+                mark(0, 2.0, None, 2),
+                mark(4, 4.0, Some(6.0), 3),
+            ]
+        );
+    }
+}
@@ -0,0 +1,432 @@
+use super::{ExportPlaylist, ExportTrack};
+use crate::mixxx::{
+    beats::BeatGrid,
+    cue::{Cue, CueType},
+    key::{Key, Mode},
+};
+use anyhow::Result;
+use quick_xml::{
+    events::{BytesDecl, BytesStart, Event},
+    Reader, Writer,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// traktor has hot cues 1..8
+const HOT_CUES: i32 = 8;
+
+/// `CUE_V2` TYPE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NmlCueType {
+    Cue = 0,
+    FadeIn = 1,
+    FadeOut = 2,
+    Load = 3,
+    Grid = 4,
+    Loop = 5,
+}
+
+impl NmlCueType {
+    fn from_i32(value: i32) -> Option<Self> {
+        match value {
+            0 => Some(Self::Cue),
+            1 => Some(Self::FadeIn),
+            2 => Some(Self::FadeOut),
+            3 => Some(Self::Load),
+            4 => Some(Self::Grid),
+            5 => Some(Self::Loop),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NmlCue {
+    pub r#type: NmlCueType,
+    /// ms
+    pub start: f32,
+    /// ms
+    pub len: f32,
+    /// -1 if not a hot cue
+    pub hotcue: i32,
+}
+
+impl NmlCue {
+    /// hot cue, load, loop and fade-in/out from intro/outro
+    pub fn from_cue(cue: &Cue, samplerate: usize) -> Option<Self> {
+        let hotcue = if (0..HOT_CUES).contains(&cue.hotcue) {
+            cue.hotcue
+        } else {
+            -1
+        };
+        let (r#type, len, hotcue) = match cue.r#type {
+            CueType::HotCue if hotcue >= 0 => (NmlCueType::Cue, 0.0, hotcue),
+            CueType::MainCue => (NmlCueType::Load, 0.0, -1),
+            CueType::Loop if cue.length > 0.0 => (
+                NmlCueType::Loop,
+                cue.length_seconds(samplerate) * 1000.0,
+                hotcue,
+            ),
+            CueType::Intro => (NmlCueType::FadeIn, 0.0, -1),
+            CueType::Outro => (NmlCueType::FadeOut, 0.0, -1),
+            _ => return None,
+        };
+        Some(Self {
+            r#type,
+            start: cue.seconds(samplerate) * 1000.0,
+            len,
+            hotcue,
+        })
+    }
+
+    /// mixxx cue type, grid markers and memory cues without a hotcue have none
+    pub fn cue_type(&self) -> Option<CueType> {
+        match self.r#type {
+            NmlCueType::Cue | NmlCueType::Loop if self.hotcue < 0 => None,
+            NmlCueType::Cue => Some(CueType::HotCue),
+            NmlCueType::Load => Some(CueType::MainCue),
+            NmlCueType::Loop => Some(CueType::Loop),
+            NmlCueType::FadeIn => Some(CueType::Intro),
+            NmlCueType::FadeOut => Some(CueType::Outro),
+            NmlCueType::Grid => None,
+        }
+    }
+}
+
+/// `ENTRY` of the collection
+#[derive(Debug, Clone, PartialEq)]
+pub struct NmlEntry {
+    pub location: PathBuf,
+    pub cues: Vec<NmlCue>,
+}
+
+/// splits a path into traktor's `VOLUME`, `DIR` and `FILE`
+fn nml_location(path: &Path, volume: &str) -> (String, String, String) {
+    let path = path.display().to_string().replace('\\', "/");
+    let (drive, path) = match path.find(':') {
+        Some(1) => (&path[..2], &path[2..]),
+        _ => ("", path.as_str()),
+    };
+    let (dir, file) = path.rsplit_once('/').unwrap_or(("", path));
+    let volume = if drive.is_empty() { volume } else { drive };
+    (
+        volume.to_string(),
+        format!("{}/", dir).replace('/', "/:"),
+        file.to_string(),
+    )
+}
+
+/// inverse of `nml_location`. paths on volumes other than windows drives
+/// and `boot_volume` are mounted under `/Volumes`
+fn location_path(volume: &str, dir: &str, file: &str, boot_volume: &str) -> PathBuf {
+    let dir = dir.replace("/:", "/");
+    if volume.len() == 2 && volume.ends_with(':') {
+        PathBuf::from(format!("{}{}{}", volume, dir, file))
+    } else if volume.is_empty() || volume == boot_volume {
+        PathBuf::from(format!("{}{}", dir, file))
+    } else {
+        PathBuf::from(format!("/Volumes/{}{}{}", volume, dir, file))
+    }
+}
+
+/// traktor `MUSICAL_KEY`: 0 = C .. 11 = B, 12 = Cm .. 23 = Bm
+fn musical_key(key: Key) -> i32 {
+    match key.mode {
+        Mode::Major => key.tonic as i32,
+        Mode::Minor => key.tonic as i32 + 12,
+    }
+}
+
+fn write_entry(w: &mut Writer<Vec<u8>>, track: &ExportTrack, volume: &str) -> Result<()> {
+    let library = &track.library;
+    let (volume, dir, file) = nml_location(&track.path, volume);
+    let grid = BeatGrid::from_library(library)?;
+    let key = Key::from_library(library)?;
+    w.create_element("ENTRY")
+        .with_attribute(("TITLE", library.title.as_str()))
+        .with_attribute(("ARTIST", library.artist.as_deref().unwrap_or_default()))
+        .write_inner_content(|w| -> Result<()> {
+            w.create_element("LOCATION")
+                .with_attribute(("DIR", dir.as_str()))
+                .with_attribute(("FILE", file.as_str()))
+                .with_attribute(("VOLUME", volume.as_str()))
+                .with_attribute(("VOLUMEID", ""))
+                .write_empty()?;
+            w.create_element("ALBUM")
+                .with_attribute(("TITLE", library.album.as_deref().unwrap_or_default()))
+                .write_empty()?;
+            w.create_element("INFO")
+                .with_attribute((
+                    "BITRATE",
+                    ((library.bitrate * 1000.0) as i64).to_string().as_str(),
+                ))
+                .with_attribute(("GENRE", library.genre.as_deref().unwrap_or_default()))
+                .with_attribute((
+                    "PLAYTIME",
+                    (library.duration.round() as i64).to_string().as_str(),
+                ))
+                .with_attribute((
+                    "PLAYTIME_FLOAT",
+                    format!("{:.6}", library.duration).as_str(),
+                ))
+                .with_attribute((
+                    "RANKING",
+                    (library.rating.clamp(0, 5) * 51).to_string().as_str(),
+                ))
+                .write_empty()?;
+            w.create_element("TEMPO")
+                .with_attribute(("BPM", format!("{:.6}", library.bpm).as_str()))
+                .with_attribute(("BPM_QUALITY", "100.000000"))
+                .write_empty()?;
+            if let Some(key) = key {
+                w.create_element("MUSICAL_KEY")
+                    .with_attribute(("VALUE", musical_key(key).to_string().as_str()))
+                    .write_empty()?;
+            }
+            let grid_cue = grid.tempo_markers().first().map(|marker| NmlCue {
+                r#type: NmlCueType::Grid,
+                start: marker.seconds * 1000.0,
+                len: 0.0,
+                hotcue: -1,
+            });
+            let cues = grid_cue.into_iter().chain(
+                track
+                    .cues
+                    .iter()
+                    .filter_map(|cue| NmlCue::from_cue(cue, library.samplerate)),
+            );
+            for cue in cues {
+                let name = if cue.r#type == NmlCueType::Grid {
+                    "AutoGrid"
+                } else {
+                    "n.n."
+                };
+                w.create_element("CUE_V2")
+                    .with_attribute(("NAME", name))
+                    .with_attribute(("DISPL_ORDER", "0"))
+                    .with_attribute(("TYPE", (cue.r#type as i32).to_string().as_str()))
+                    .with_attribute(("START", format!("{:.6}", cue.start).as_str()))
+                    .with_attribute(("LEN", format!("{:.6}", cue.len).as_str()))
+                    .with_attribute(("REPEATS", "-1"))
+                    .with_attribute(("HOTCUE", cue.hotcue.to_string().as_str()))
+                    .write_empty()?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+/// NML with a collection of every track in the playlists.
+/// `volume` is used for paths without a windows drive
+pub fn render(playlists: &[ExportPlaylist], volume: &str) -> Result<String> {
+    let collection = playlists
+        .iter()
+        .flat_map(|playlist| playlist.tracks.iter())
+        .map(|track| (track.library.id, track))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut writer = Writer::new_with_indent(vec![], b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new(
+        "1.0",
+        Some("UTF-8"),
+        Some("no"),
+    )))?;
+    writer
+        .create_element("NML")
+        .with_attribute(("VERSION", "19"))
+        .write_inner_content(|w| -> Result<()> {
+            w.create_element("HEAD")
+                .with_attribute(("COMPANY", "www.native-instruments.com"))
+                .with_attribute(("PROGRAM", "Traktor"))
+                .write_empty()?;
+            w.create_element("COLLECTION")
+                .with_attribute(("ENTRIES", collection.len().to_string().as_str()))
+                .write_inner_content(|w| -> Result<()> {
+                    for track in collection.values() {
+                        write_entry(w, track, volume)?;
+                    }
+                    Ok(())
+                })?;
+            w.create_element("PLAYLISTS")
+                .write_inner_content(|w| -> Result<()> {
+                    w.create_element("NODE")
+                        .with_attribute(("TYPE", "FOLDER"))
+                        .with_attribute(("NAME", "$ROOT"))
+                        .write_inner_content(|w| -> Result<()> {
+                            w.create_element("SUBNODES")
+                                .with_attribute(("COUNT", playlists.len().to_string().as_str()))
+                                .write_inner_content(|w| -> Result<()> {
+                                    for playlist in playlists.iter() {
+                                        write_playlist(w, playlist, volume)?;
+                                    }
+                                    Ok(())
+                                })?;
+                            Ok(())
+                        })?;
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(String::from_utf8(writer.into_inner())? + "\n")
+}
+
+fn write_playlist(w: &mut Writer<Vec<u8>>, playlist: &ExportPlaylist, volume: &str) -> Result<()> {
+    w.create_element("NODE")
+        .with_attribute(("TYPE", "PLAYLIST"))
+        .with_attribute(("NAME", playlist.name.as_str()))
+        .write_inner_content(|w| -> Result<()> {
+            w.create_element("PLAYLIST")
+                .with_attribute(("ENTRIES", playlist.tracks.len().to_string().as_str()))
+                .with_attribute(("TYPE", "LIST"))
+                .write_inner_content(|w| -> Result<()> {
+                    for track in playlist.tracks.iter() {
+                        let (volume, dir, file) = nml_location(&track.path, volume);
+                        w.create_element("ENTRY")
+                            .write_inner_content(|w| -> Result<()> {
+                                w.create_element("PRIMARYKEY")
+                                    .with_attribute(("TYPE", "TRACK"))
+                                    .with_attribute((
+                                        "KEY",
+                                        format!("{}{}{}", volume, dir, file).as_str(),
+                                    ))
+                                    .write_empty()?;
+                                Ok(())
+                            })?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn attribute(e: &BytesStart, name: &str) -> Result<String> {
+    Ok(e.try_get_attribute(name)?
+        .map(|attr| attr.unescape_value().map(|v| v.to_string()))
+        .transpose()?
+        .unwrap_or_default())
+}
+
+/// reads location and cues of each collection entry.
+/// `boot_volume` is the volume of paths without a mount point
+pub fn parse(nml: &str, boot_volume: &str) -> Result<Vec<NmlEntry>> {
+    let mut reader = Reader::from_str(nml);
+    let mut entries = vec![];
+    let mut in_collection = false;
+    let mut current: Option<NmlEntry> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) | Event::Empty(e) => match e.name().as_ref() {
+                b"COLLECTION" => in_collection = true,
+                b"ENTRY" if in_collection => {
+                    if let Some(entry) = current.take() {
+                        entries.push(entry);
+                    }
+                    current = Some(NmlEntry {
+                        location: PathBuf::new(),
+                        cues: vec![],
+                    });
+                }
+                b"LOCATION" => {
+                    if let Some(entry) = current.as_mut() {
+                        entry.location = location_path(
+                            &attribute(&e, "VOLUME")?,
+                            &attribute(&e, "DIR")?,
+                            &attribute(&e, "FILE")?,
+                            boot_volume,
+                        );
+                    }
+                }
+                b"CUE_V2" => {
+                    let r#type = NmlCueType::from_i32(attribute(&e, "TYPE")?.parse()?);
+                    if let (Some(entry), Some(r#type)) = (current.as_mut(), r#type) {
+                        entry.cues.push(NmlCue {
+                            r#type,
+                            start: attribute(&e, "START")?.parse()?,
+                            len: attribute(&e, "LEN")?.parse().unwrap_or(0.0),
+                            hotcue: attribute(&e, "HOTCUE")?.parse().unwrap_or(-1),
+                        });
+                    }
+                }
+                _ => {}
+            },
+            Event::End(e) if e.name().as_ref() == b"COLLECTION" => {
+                in_collection = false;
+                entries.extend(current.take());
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{location_path, nml_location, parse, NmlCue, NmlCueType};
+    use crate::mixxx::cue::{Cue, CueType};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_nml_location() {
+        for (path, volume) in [
+            ("/Users/me/a b.mp3", "Macintosh HD"),
+            ("F:/music/a.mp3", ""),
+        ] {
+            let (volume, dir, file) = nml_location(Path::new(path), volume);
+            assert_eq!(
+                location_path(&volume, &dir, &file, "Macintosh HD"),
+                PathBuf::from(path)
+            );
+        }
+        assert_eq!(
+            location_path("USB", "/:music/:", "a.mp3", "Macintosh HD"),
+            PathBuf::from("/Volumes/USB/music/a.mp3")
+        );
+        assert_eq!(
+            nml_location(Path::new("F:/music/a.mp3"), ""),
+            (
+                "F:".to_string(),
+                "/:music/:".to_string(),
+                "a.mp3".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let nml = r#"<?xml version="1.0" encoding="UTF-8" standalone="no" ?>
+<NML VERSION="19"><HEAD COMPANY="www.native-instruments.com" PROGRAM="Traktor"></HEAD>
+<COLLECTION ENTRIES="1"><ENTRY TITLE="a">
+<LOCATION DIR="/:music/:" FILE="a.mp3" VOLUME="F:" VOLUMEID=""></LOCATION>
+<CUE_V2 NAME="AutoGrid" DISPL_ORDER="0" TYPE="4" START="25.0" LEN="0.0" REPEATS="-1" HOTCUE="0"></CUE_V2>
+<CUE_V2 NAME="n.n." DISPL_ORDER="0" TYPE="5" START="1000.0" LEN="2000.0" REPEATS="-1" HOTCUE="3"></CUE_V2>
+<CUE_V2 NAME="memory" DISPL_ORDER="0" TYPE="0" START="3000.0" LEN="0.0" REPEATS="-1" HOTCUE="-1"></CUE_V2>
+</ENTRY></COLLECTION>
+<PLAYLISTS><NODE TYPE="FOLDER" NAME="$ROOT"><SUBNODES COUNT="1"><NODE TYPE="PLAYLIST" NAME="set">
+<PLAYLIST ENTRIES="1" TYPE="LIST"><ENTRY><PRIMARYKEY TYPE="TRACK" KEY="F:/:music/:a.mp3"></PRIMARYKEY></ENTRY>
+</PLAYLIST></NODE></SUBNODES></NODE></PLAYLISTS></NML>"#;
+        let entries = parse(nml, "").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].location, PathBuf::from("F:/music/a.mp3"));
+        assert_eq!(entries[0].cues.len(), 3);
+        assert_eq!(entries[0].cues[1].cue_type(), Some(CueType::Loop));
+        assert_eq!(entries[0].cues[2].cue_type(), None);
+
+        let cue = Cue {
+            id: 0,
+            track_id: 1,
+            r#type: CueType::Loop,
+            position: 200.0,
+            length: 400.0,
+            hotcue: 3,
+        };
+        assert_eq!(
+            NmlCue::from_cue(&cue, 100),
+            Some(entries[0].cues[1].clone())
+        );
+        assert_eq!(entries[0].cues[0].r#type, NmlCueType::Grid);
+    }
+}
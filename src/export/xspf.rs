@@ -0,0 +1,54 @@
+use super::{export_path, file_uri, ExportPlaylist};
+use anyhow::Result;
+use quick_xml::{
+    events::{BytesDecl, BytesText, Event},
+    Writer,
+};
+use std::path::Path;
+
+pub fn render(playlist: &ExportPlaylist, base_dir: Option<&Path>) -> Result<String> {
+    let mut writer = Writer::new_with_indent(vec![], b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer
+        .create_element("playlist")
+        .with_attribute(("version", "1"))
+        .with_attribute(("xmlns", "http://xspf.org/ns/0/"))
+        .write_inner_content(|w| -> Result<()> {
+            w.create_element("title")
+                .write_text_content(BytesText::new(&playlist.name))?;
+            w.create_element("trackList")
+                .write_inner_content(|w| -> Result<()> {
+                    for track in playlist.tracks.iter() {
+                        let path = export_path(&track.path, base_dir);
+                        w.create_element("track")
+                            .write_inner_content(|w| -> Result<()> {
+                                w.create_element("location")
+                                    .write_text_content(BytesText::new(&file_uri(&path, "")))?;
+                                w.create_element("title")
+                                    .write_text_content(BytesText::new(&track.library.title))?;
+                                if let Some(artist) =
+                                    track.library.artist.as_ref().filter(|a| !a.is_empty())
+                                {
+                                    w.create_element("creator")
+                                        .write_text_content(BytesText::new(artist))?;
+                                }
+                                if let Some(album) =
+                                    track.library.album.as_ref().filter(|a| !a.is_empty())
+                                {
+                                    w.create_element("album")
+                                        .write_text_content(BytesText::new(album))?;
+                                }
+                                w.create_element("duration")
+                                    .write_text_content(BytesText::new(
+                                        &((track.library.duration * 1000.0).round() as i64)
+                                            .to_string(),
+                                    ))?;
+                                Ok(())
+                            })?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(String::from_utf8(writer.into_inner())? + "\n")
+}
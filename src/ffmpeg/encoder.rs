@@ -0,0 +1,145 @@
+use anyhow::Result;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Mp3,
+    Flac,
+    Wav,
+    Ogg,
+    M4a,
+}
+
+impl OutputFormat {
+    fn from_path(path: &Path) -> Result<Self> {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "mp3" => Ok(Self::Mp3),
+            "flac" => Ok(Self::Flac),
+            "wav" => Ok(Self::Wav),
+            "ogg" | "oga" => Ok(Self::Ogg),
+            "m4a" | "mp4" => Ok(Self::M4a),
+            _ => anyhow::bail!("unknown format of {}, use --format", path.display()),
+        }
+    }
+
+    /// `-f` muxer and `-c:a` codec
+    fn muxer_codec(&self) -> (&'static str, &'static str) {
+        match self {
+            Self::Mp3 => ("mp3", "libmp3lame"),
+            Self::Flac => ("flac", "flac"),
+            Self::Wav => ("wav", "pcm_s16le"),
+            Self::Ogg => ("ogg", "libvorbis"),
+            Self::M4a => ("ipod", "aac"),
+        }
+    }
+}
+
+/// extension of rendered mix segments
+pub const SEGMENT_EXTENSION: &str = "wav";
+/// sample rate of every part of a mix
+pub const MIX_SAMPLE_RATE: u32 = 44100;
+
+/// output args of rendered mix segments, lossless 32 bit float with one sample rate,
+/// so only the concatenated mix is encoded
+pub fn segment_args() -> Vec<String> {
+    [
+        "-c:a",
+        "pcm_f32le",
+        "-ar",
+        &MIX_SAMPLE_RATE.to_string(),
+        "-ac",
+        "2",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// container and codec settings of the final output
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Encoding {
+    /// output container and codec, from the extension of `--out` by default
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+    /// kbps of mp3, ogg and m4a
+    #[arg(long, conflicts_with = "quality")]
+    pub bitrate: Option<u32>,
+    /// variable bitrate quality of mp3 (0 best to 9) and ogg (-1 to 10 best),
+    /// compression level of flac (0 to 12)
+    #[arg(long, allow_hyphen_values = true)]
+    pub quality: Option<f32>,
+}
+
+impl Encoding {
+    pub fn format(&self, out: &Path) -> Result<OutputFormat> {
+        match self.format {
+            Some(format) => Ok(format),
+            None => OutputFormat::from_path(out),
+        }
+    }
+
+    /// output args before the output path of ffmpeg
+    pub fn args(&self, out: &Path) -> Result<Vec<String>> {
+        let format = self.format(out)?;
+        let (muxer, codec) = format.muxer_codec();
+        let mut args = vec!["-f", muxer, "-c:a", codec]
+            .into_iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+        match (format, self.bitrate, self.quality) {
+            (_, None, None) => {}
+            (OutputFormat::Mp3 | OutputFormat::Ogg | OutputFormat::M4a, Some(bitrate), _) => {
+                args.extend(["-b:a".to_string(), format!("{}k", bitrate)]);
+            }
+            (OutputFormat::Mp3 | OutputFormat::Ogg, None, Some(quality)) => {
+                args.extend(["-q:a".to_string(), quality.to_string()]);
+            }
+            (OutputFormat::Flac, None, Some(quality)) => {
+                args.extend(["-compression_level".to_string(), quality.to_string()]);
+            }
+            _ => anyhow::bail!(
+                "--{} is not supported for {}",
+                if self.bitrate.is_some() {
+                    "bitrate"
+                } else {
+                    "quality"
+                },
+                format!("{:?}", format).to_lowercase()
+            ),
+        }
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Encoding, OutputFormat};
+    use std::path::Path;
+
+    #[test]
+    fn test_encoding_args() {
+        let encoding = Encoding {
+            bitrate: Some(320),
+            ..Default::default()
+        };
+        assert_eq!(
+            encoding.args(Path::new("mix.MP3")).unwrap(),
+            vec!["-f", "mp3", "-c:a", "libmp3lame", "-b:a", "320k"]
+        );
+        assert!(encoding.args(Path::new("mix.wav")).is_err());
+        assert!(encoding.args(Path::new("mix")).is_err());
+
+        let encoding = Encoding {
+            format: Some(OutputFormat::Flac),
+            quality: Some(8.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            encoding.args(Path::new("mix.mp3")).unwrap(),
+            vec!["-f", "flac", "-c:a", "flac", "-compression_level", "8"]
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use std::{fmt::Display, process::ExitStatus};
+
+/// a finished ffmpeg process that did not succeed
+#[derive(Debug)]
+pub struct FfmpegError {
+    /// the full command line, quoted for a shell
+    pub command: String,
+    pub status: ExitStatus,
+    /// last lines of stderr
+    pub stderr: String,
+}
+
+impl Display for FfmpegError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})\n{}", self.command, self.status, self.stderr)
+    }
+}
+
+impl std::error::Error for FfmpegError {}
+
+fn quote(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c));
+    if plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+pub fn command_line(program: &str, args: &[String]) -> String {
+    std::iter::once(program)
+        .chain(args.iter().map(|arg| arg.as_str()))
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::command_line;
+
+    #[test]
+    fn test_command_line() {
+        let args = ["-i", "my track's.mp3", "-filter_complex", "[0:a]anull[p0]"].map(String::from);
+        assert_eq!(
+            command_line("ffmpeg", &args),
+            r"ffmpeg -i 'my track'\''s.mp3' -filter_complex '[0:a]anull[p0]'"
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt::Display;
+
+/// escapes `\`, `'` and each of `chars` with a backslash
+fn escape(value: &str, chars: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || c == '\'' || chars.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// a link label, used once as an output and once as an input
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pad(String);
+
+impl Pad {
+    /// audio of the `index`th `-i`
+    pub fn input(index: usize) -> Self {
+        Self(format!("{}:a", index))
+    }
+}
+
+impl Display for Pad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}]", self.0)
+    }
+}
+
+/// a filter with `key=value` options
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    name: String,
+    options: Vec<(String, String)>,
+}
+
+impl Filter {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            options: vec![],
+        }
+    }
+
+    pub fn arg(mut self, key: &str, value: impl Display) -> Self {
+        self.options.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// the target of `asendcmd`
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for Filter {
+    /// escapes values for the option parser, then for the filter graph parser
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        for (i, (key, value)) in self.options.iter().enumerate() {
+            let value = escape(&escape(value, &[':']), &['[', ']', ',', ';']);
+            let separator = if i == 0 { '=' } else { ':' };
+            write!(f, "{}{}={}", separator, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// `asendcmd` setting `option` of the filter named `target` to each value at its time in seconds
+pub fn send_commands(target: &str, option: &str, points: &[(f32, f32)]) -> Filter {
+    let commands = points
+        .iter()
+        .map(|(time, value)| format!("{} {} {} {}", time, target, option, value))
+        .collect::<Vec<_>>();
+    Filter::new("asendcmd").arg("commands", commands.join(";"))
+}
+
+#[derive(Debug)]
+struct Chain {
+    inputs: Vec<Pad>,
+    filters: Vec<Filter>,
+    outputs: Vec<Pad>,
+}
+
+/// `-filter_complex` graph with generated link labels
+#[derive(Debug, Default)]
+pub struct FilterGraph {
+    chains: Vec<Chain>,
+    pads: usize,
+    instances: usize,
+}
+
+impl FilterGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds `filters` as one chain reading `inputs`, returns `outputs` new pads
+    pub fn add(&mut self, inputs: Vec<Pad>, filters: Vec<Filter>, outputs: usize) -> Vec<Pad> {
+        let pads = (0..outputs)
+            .map(|i| Pad(format!("p{}", self.pads + i)))
+            .collect::<Vec<_>>();
+        self.pads += outputs;
+        let returned = pads.iter().map(|pad| Pad(pad.0.clone())).collect();
+        self.chains.push(Chain {
+            inputs,
+            filters,
+            outputs: pads,
+        });
+        returned
+    }
+
+    /// names `filter` uniquely, commands are sent to every filter of a name in the graph
+    pub fn instance(&mut self, filter: Filter) -> Filter {
+        self.instances += 1;
+        Filter {
+            name: format!("{}@i{}", filter.name, self.instances),
+            ..filter
+        }
+    }
+
+    /// adds a chain with one output, passes `inputs` through when `filters` is empty
+    pub fn chain(&mut self, mut inputs: Vec<Pad>, filters: Vec<Filter>) -> Pad {
+        if filters.is_empty() && inputs.len() == 1 {
+            return inputs.remove(0);
+        }
+        self.add(inputs, filters, 1).remove(0)
+    }
+}
+
+impl Display for FilterGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, chain) in self.chains.iter().enumerate() {
+            if i > 0 {
+                write!(f, ";")?;
+            }
+            for pad in chain.inputs.iter() {
+                write!(f, "{}", pad)?;
+            }
+            let filters = chain
+                .filters
+                .iter()
+                .map(|filter| filter.to_string())
+                .collect::<Vec<_>>();
+            write!(f, "{}", filters.join(","))?;
+            for pad in chain.outputs.iter() {
+                write!(f, "{}", pad)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{send_commands, Filter, FilterGraph, Pad};
+
+    #[test]
+    fn test_filter_escape() {
+        let filter = Filter::new("drawtext").arg("text", "it's 1:2, [a];b\\");
+        assert_eq!(
+            filter.to_string(),
+            r"drawtext=text=it\\\'s 1\\:2\, \[a\]\;b\\\\"
+        );
+        assert_eq!(
+            Filter::new("afade")
+                .arg("t", "in")
+                .arg("d", 1.5)
+                .to_string(),
+            "afade=t=in:d=1.5"
+        );
+    }
+
+    #[test]
+    fn test_send_commands() {
+        assert_eq!(
+            send_commands("highpass@a", "f", &[(0.0, 20.0), (0.5, 40.0)]).to_string(),
+            r"asendcmd=commands=0 highpass@a f 20\;0.5 highpass@a f 40"
+        );
+        let mut graph = FilterGraph::new();
+        let first = graph.instance(Filter::new("highpass").arg("f", 20));
+        let second = graph.instance(Filter::new("highpass").arg("f", 20));
+        assert_eq!(first.to_string(), "highpass@i1=f=20");
+        assert_eq!(second.name(), "highpass@i2");
+    }
+
+    #[test]
+    fn test_filter_graph() {
+        let mut graph = FilterGraph::new();
+        let a = graph.chain(
+            vec![Pad::input(0)],
+            vec![
+                Filter::new("loudnorm"),
+                Filter::new("atempo").arg("tempo", 1.25),
+            ],
+        );
+        let a = graph.chain(vec![a], vec![]);
+        let b = graph.chain(vec![Pad::input(1)], vec![Filter::new("anull")]);
+        let mut split = graph.add(vec![b], vec![Filter::new("asplit")], 2);
+        let (b1, b2) = (split.remove(0), split.remove(0));
+        let out = graph.chain(vec![a, b1, b2], vec![Filter::new("amix").arg("inputs", 3)]);
+        assert_eq!(out, Pad("p4".to_string()));
+        assert_eq!(
+            graph.to_string(),
+            [
+                "[0:a]loudnorm,atempo=tempo=1.25[p0]",
+                "[1:a]anull[p1]",
+                "[p1]asplit[p2][p3]",
+                "[p0][p2][p3]amix=inputs=3[p4]",
+            ]
+            .join(";")
+        );
+    }
+}
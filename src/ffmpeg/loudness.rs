@@ -0,0 +1,141 @@
+use super::{filter_graph::Filter, run_stderr};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// reference of mixxx's ReplayGain 2.0 analysis
+const REPLAYGAIN_REFERENCE_LUFS: f32 = -18.0;
+
+/// `loudnorm=print_format=json` output, numbers as strings
+#[derive(Debug, Deserialize)]
+struct LoudnormJson {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+}
+
+/// EBU R128 measurement of a whole file
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// integrated loudness, LUFS
+    pub integrated: f32,
+    /// dBTP, unknown for replaygain
+    pub true_peak: Option<f32>,
+    /// loudness range, LU
+    pub range: Option<f32>,
+}
+
+impl Loudness {
+    /// the last JSON object in loudnorm's stderr
+    fn from_stderr(stderr: &str) -> Result<Self> {
+        let json = stderr
+            .rfind('{')
+            .and_then(|start| {
+                let end = stderr[start..].find('}')?;
+                Some(&stderr[start..=start + end])
+            })
+            .ok_or(anyhow::anyhow!("no loudnorm measurement"))?;
+        let json: LoudnormJson = serde_json::from_str(json)?;
+        Ok(Self {
+            integrated: json.input_i.trim().parse()?,
+            true_peak: Some(json.input_tp.trim().parse()?),
+            range: Some(json.input_lra.trim().parse()?),
+        })
+    }
+
+    /// from mixxx's `library.replaygain` ratio, `None` when not analyzed
+    pub fn from_replaygain(replaygain: f32) -> Option<Self> {
+        (replaygain > 0.0).then(|| Self {
+            integrated: REPLAYGAIN_REFERENCE_LUFS - 20.0 * replaygain.log10(),
+            true_peak: None,
+            range: None,
+        })
+    }
+
+    /// dB of linear gain reaching `target` LUFS without exceeding `true_peak` dBTP
+    pub fn gain(&self, target: f32, true_peak: f32) -> f32 {
+        let gain = match self.true_peak {
+            Some(peak) => (target - self.integrated).min(true_peak - peak),
+            None => target - self.integrated,
+        };
+        // silence
+        if gain.is_finite() {
+            gain
+        } else {
+            0.0
+        }
+    }
+
+    /// dB the `true_peak` limit takes off the gain reaching `target` LUFS
+    pub fn peak_cut(&self, target: f32, true_peak: f32) -> f32 {
+        let cut = target - self.integrated - self.gain(target, true_peak);
+        if cut.is_finite() {
+            cut
+        } else {
+            0.0
+        }
+    }
+}
+
+/// first pass of loudnorm over the first audio stream of `path`
+pub fn measure_loudness(path: &Path) -> Result<Loudness> {
+    let args = [
+        "-hide_banner",
+        "-nostats",
+        "-loglevel",
+        "info",
+        "-i",
+        &path.display().to_string(),
+        "-map",
+        "0:a:0",
+        "-af",
+        &Filter::new("loudnorm")
+            .arg("print_format", "json")
+            .to_string(),
+        "-f",
+        "null",
+        "-",
+    ]
+    .map(String::from);
+    let stderr = run_stderr("ffmpeg", &args)?;
+    Loudness::from_stderr(&stderr).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Loudness;
+
+    #[test]
+    fn test_loudness() {
+        let stderr = r#"Input #0, mp3, from 'a.mp3':
+[Parsed_loudnorm_0 @ 0x5581]
+{
+	"input_i" : "-9.52",
+	"input_tp" : "0.31",
+	"input_lra" : "4.10",
+	"input_thresh" : "-19.71",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.06"
+}
+"#;
+        let loudness = Loudness::from_stderr(stderr).unwrap();
+        assert_eq!(
+            loudness,
+            Loudness {
+                integrated: -9.52,
+                true_peak: Some(0.31),
+                range: Some(4.1),
+            }
+        );
+        // limited by the true peak
+        assert!((loudness.gain(-8.0, -1.0) - -1.31).abs() < 1e-4);
+        assert!((loudness.gain(-14.0, -1.0) - -4.48).abs() < 1e-4);
+        assert!((loudness.peak_cut(-8.0, -1.0) - 2.83).abs() < 1e-4);
+        assert_eq!(loudness.peak_cut(-14.0, -1.0), 0.0);
+        assert!(Loudness::from_stderr("no json").is_err());
+
+        let replaygain = Loudness::from_replaygain(0.5).unwrap();
+        assert!((replaygain.integrated - -11.979).abs() < 0.01);
+        assert_eq!(Loudness::from_replaygain(0.0), None);
+    }
+}
@@ -1,72 +1,274 @@
+use self::{
+    encoder::Encoding,
+    error::{command_line, FfmpegError},
+    filter_graph::{FilterGraph, Pad},
+    progress::progress_seconds,
+};
 use anyhow::Result;
-use std::{path::Path, process::Command};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
 
+pub mod encoder;
+pub mod error;
+pub mod filter_graph;
+pub mod loudness;
+pub mod probe;
+pub mod progress;
 pub mod stepped_tempo_filter;
 
-fn ffmpeg(args: Vec<String>) -> Result<()> {
-    let args = vec![
+const LOGLEVELS: [&str; 9] = [
+    "quiet", "panic", "fatal", "error", "warning", "info", "verbose", "debug", "trace",
+];
+/// lines of stderr kept for `FfmpegError`
+const STDERR_TAIL_LINES: usize = 20;
+
+/// `-loglevel` from `FFMPEG_LOGLEVEL`, warning by default
+fn loglevel() -> Result<String> {
+    let level = std::env::var("FFMPEG_LOGLEVEL").unwrap_or("warning".to_string());
+    if !LOGLEVELS.contains(&level.as_str()) {
+        anyhow::bail!(
+            "invalid FFMPEG_LOGLEVEL {}, expected one of {}",
+            level,
+            LOGLEVELS.join(", ")
+        );
+    }
+    Ok(level)
+}
+
+fn read_lines(reader: impl std::io::Read, mut f: impl FnMut(String) -> Result<()>) -> Result<()> {
+    for line in BufReader::new(reader).split(b'\n') {
+        f(String::from_utf8_lossy(&line?).trim_end().to_string())?;
+    }
+    Ok(())
+}
+
+/// runs `program` passing its stderr through and each stdout line to `on_stdout`,
+/// fails with `FfmpegError`. an error of `on_stdout` kills the process
+fn run(program: &str, args: &[String], on_stdout: impl FnMut(String) -> Result<()>) -> Result<()> {
+    let command = command_line(program, args);
+    log::debug!("{}", command);
+    let mut child = Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("failed to run {}: {}", program, e))?;
+    let stderr = child.stderr.take();
+    let tail = std::thread::spawn(move || -> Result<VecDeque<String>> {
+        let mut tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+        if let Some(stderr) = stderr {
+            read_lines(stderr, |line| {
+                eprintln!("{}", line);
+                if tail.len() == STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+                Ok(())
+            })?;
+        }
+        Ok(tail)
+    });
+    let read = match child.stdout.take() {
+        Some(stdout) => read_lines(stdout, on_stdout),
+        None => Ok(()),
+    };
+    if read.is_err() {
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    let tail = tail
+        .join()
+        .map_err(|_| anyhow::anyhow!("failed to read stderr of {}", program))??;
+    read?;
+    if !status.success() {
+        let stderr = Vec::from(tail).join("\n");
+        return Err(FfmpegError {
+            command,
+            status,
+            stderr,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// runs `program` to the end and returns its stderr, fails with `FfmpegError`
+fn run_stderr(program: &str, args: &[String]) -> Result<String> {
+    let command = command_line(program, args);
+    log::debug!("{}", command);
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| anyhow::anyhow!("failed to run {}: {}", program, e))?;
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    if !output.status.success() {
+        let lines = stderr.lines().collect::<Vec<_>>();
+        return Err(FfmpegError {
+            command,
+            status: output.status,
+            stderr: lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n"),
+        }
+        .into());
+    }
+    Ok(stderr)
+}
+
+/// first line of `ffmpeg -version`
+pub fn ffmpeg_version() -> Result<String> {
+    let mut lines = vec![];
+    run("ffmpeg", &["-version".to_string()], |line| {
+        lines.push(line);
+        Ok(())
+    })?;
+    lines
+        .into_iter()
+        .next()
+        .ok_or(anyhow::anyhow!("no output of ffmpeg -version"))
+}
+
+/// whether `ffmpeg -filters` lists `name`, as "<flags> <name> <io> <description>"
+pub fn has_filter(name: &str) -> Result<bool> {
+    let mut found = false;
+    let args = ["-hide_banner", "-filters"].map(String::from);
+    run("ffmpeg", &args, |line| {
+        found |= line.split_whitespace().nth(1) == Some(name);
+        Ok(())
+    })?;
+    Ok(found)
+}
+
+/// calls `progress` with seconds of output written, an error stops ffmpeg
+fn ffmpeg(
+    args: Vec<String>,
+    output: &Path,
+    mut progress: impl FnMut(f32) -> Result<()>,
+) -> Result<()> {
+    let args = [
         vec![
             "-loglevel".to_string(),
-            // "warning".to_string(),
-            "verbose".to_string(),
+            loglevel()?,
+            "-nostats".to_string(),
+            "-progress".to_string(),
+            "pipe:1".to_string(),
             "-y".to_string(),
         ],
         args,
     ]
     .concat();
-    println!("{:#?}", args);
-    let child = Command::new("ffmpeg").args(args).spawn()?;
-    let cmd_output = child.wait_with_output()?;
-    if !cmd_output.status.success() {
-        println!("{}", String::from_utf8_lossy(&cmd_output.stderr));
+    let result = run("ffmpeg", &args, |line| match progress_seconds(&line) {
+        Some(seconds) => progress(seconds),
+        None => Ok(()),
+    });
+    if result.is_err() && output.exists() {
+        // would be taken as rendered by the next run
+        std::fs::remove_file(output)?;
+    }
+    result
+}
+
+/// a `-i` file, seeked to `range` seconds when given
+#[derive(Debug, Clone)]
+pub struct Input {
+    pub path: PathBuf,
+    pub range: Option<(f32, f32)>,
+}
+
+impl Input {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            range: None,
+        }
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if let Some((from, to)) = self.range {
+            args.extend(["-ss".to_string(), from.to_string()]);
+            args.extend(["-to".to_string(), to.to_string()]);
+        }
+        args.extend(["-i".to_string(), self.path.display().to_string()]);
+        args
     }
-    Ok(())
 }
 
 pub fn ffmpeg_complex_filter(
-    inputs: Vec<&Path>,
+    inputs: Vec<Input>,
     output: &Path,
-    filters: Vec<String>,
+    graph: &FilterGraph,
+    out: &Pad,
+    output_args: Vec<String>,
+    progress: impl FnMut(f32) -> Result<()>,
 ) -> Result<()> {
-    let args = vec![
-        inputs
-            .into_iter()
-            .flat_map(|i| vec!["-i".to_string(), i.display().to_string()])
-            .collect(),
+    let args = [
+        inputs.into_iter().flat_map(|input| input.args()).collect(),
         vec![
             "-filter_complex".to_string(),
-            filters.join(";"),
+            graph.to_string(),
             "-map".to_string(),
-            format!("[{}]", "out"),
-            output.display().to_string(),
+            out.to_string(),
         ],
+        output_args,
+        vec![output.display().to_string()],
     ]
     .concat();
-    ffmpeg(args)
+    ffmpeg(args, output, progress)
 }
 
-pub fn concat_cmd(file_list_path: &Path, out: &Path) -> Result<()> {
-    let args = vec![
+pub fn concat_cmd(
+    file_list_path: &Path,
+    out: &Path,
+    encoding: &Encoding,
+    progress: impl FnMut(f32) -> Result<()>,
+) -> Result<()> {
+    let mut args = vec![
         "-f".to_string(),
         "concat".to_string(),
+        // allows absolute paths of the cache directory
+        "-safe".to_string(),
+        "0".to_string(),
         "-i".to_string(),
         file_list_path.display().to_string(),
-        out.display().to_string(),
     ];
-    ffmpeg(args)
+    args.extend(encoding.args(out)?);
+    args.push(out.display().to_string());
+    ffmpeg(args, out, progress)
 }
 
-pub fn slice_cmd(a_path: &Path, filters: &[String], a_range: (f32, f32), out: &Path) -> Result<()> {
-    let args = vec![
-        "-ss".to_string(),
-        a_range.0.to_string(),
-        "-to".to_string(),
-        a_range.1.to_string(),
-        "-i".to_string(),
-        a_path.display().to_string(),
-        "-filter_complex".to_string(),
-        filters.join(";"),
-        out.display().to_string(),
-    ];
-    ffmpeg(args)
+#[cfg(test)]
+mod tests {
+    use super::{error::FfmpegError, run};
+
+    #[test]
+    fn test_run_error() {
+        let script = "for i in $(seq 1 30); do echo line $i >&2; done; exit 3";
+        let args = ["-c", script].map(String::from);
+        let err = run("sh", &args, |_| Ok(())).unwrap_err();
+        let err = err.downcast::<FfmpegError>().unwrap();
+        assert_eq!(err.status.code(), Some(3));
+        assert!(err.command.starts_with("sh -c 'for i in"));
+        assert_eq!(err.stderr.lines().count(), 20);
+        assert!(err.stderr.starts_with("line 11\n"));
+        assert!(err.stderr.ends_with("line 30"));
+        let mut stdout = vec![];
+        let args = ["-c", "echo out; echo err >&2"].map(String::from);
+        run("sh", &args, |line| {
+            stdout.push(line);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(stdout, vec!["out"]);
+
+        // stopped by the callback instead of sleeping
+        let args = ["-c", "echo out; sleep 10"].map(String::from);
+        let started = std::time::Instant::now();
+        let err = run("sh", &args, |_| anyhow::bail!("cancelled")).unwrap_err();
+        assert_eq!(err.to_string(), "cancelled");
+        assert!(started.elapsed().as_secs() < 5);
+    }
 }
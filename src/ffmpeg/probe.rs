@@ -0,0 +1,116 @@
+use super::run;
+use anyhow::Result;
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+struct ProbeStream {
+    codec_name: Option<String>,
+    sample_rate: Option<String>,
+    channels: Option<u32>,
+    bit_rate: Option<String>,
+    duration: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbeFormat {
+    duration: Option<String>,
+    bit_rate: Option<String>,
+}
+
+/// `ffprobe -of json` output
+#[derive(Debug, Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+/// the first audio stream of a file
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamInfo {
+    /// seconds
+    pub duration: f32,
+    pub sample_rate: usize,
+    pub channels: u32,
+    pub codec: String,
+    /// bits per second
+    pub bitrate: Option<u32>,
+}
+
+fn parse<T: std::str::FromStr>(value: Option<String>) -> Option<T> {
+    value.and_then(|v| v.parse().ok())
+}
+
+impl StreamInfo {
+    fn from_json(json: &str) -> Result<Self> {
+        let probe: Probe = serde_json::from_str(json)?;
+        let stream = probe
+            .streams
+            .into_iter()
+            .next()
+            .ok_or(anyhow::anyhow!("no audio stream"))?;
+        let format = probe.format;
+        let duration = parse(stream.duration)
+            .or_else(|| parse(format.as_ref().and_then(|f| f.duration.clone())))
+            .ok_or(anyhow::anyhow!("unknown duration"))?;
+        Ok(Self {
+            duration,
+            sample_rate: parse(stream.sample_rate).ok_or(anyhow::anyhow!("unknown sample rate"))?,
+            channels: stream.channels.ok_or(anyhow::anyhow!("unknown channels"))?,
+            codec: stream.codec_name.unwrap_or_default(),
+            bitrate: parse(stream.bit_rate).or_else(|| parse(format.and_then(|f| f.bit_rate))),
+        })
+    }
+}
+
+pub fn ffprobe(path: &Path) -> Result<StreamInfo> {
+    let args = [
+        "-v",
+        "error",
+        "-select_streams",
+        "a:0",
+        "-show_entries",
+        "stream=codec_name,sample_rate,channels,bit_rate,duration:format=duration,bit_rate",
+        "-of",
+        "json",
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .chain([path.display().to_string()])
+    .collect::<Vec<_>>();
+    let mut json = String::new();
+    run("ffprobe", &args, |line| {
+        json.push_str(&line);
+        json.push('\n');
+        Ok(())
+    })?;
+    StreamInfo::from_json(&json).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamInfo;
+
+    #[test]
+    fn test_stream_info() {
+        let json = r#"{
+            "programs": [],
+            "streams": [
+                { "codec_name": "mp3", "sample_rate": "44100", "channels": 1, "bit_rate": "128000" }
+            ],
+            "format": { "duration": "241.632653", "bit_rate": "130123" }
+        }"#;
+        assert_eq!(
+            StreamInfo::from_json(json).unwrap(),
+            StreamInfo {
+                duration: 241.63266,
+                sample_rate: 44100,
+                channels: 1,
+                codec: "mp3".to_string(),
+                bitrate: Some(128000),
+            }
+        );
+        assert!(StreamInfo::from_json(r#"{ "streams": [] }"#).is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use kdam::{tqdm, Bar, BarExt};
+
+/// seconds of output written, from a `-progress` line
+pub fn progress_seconds(line: &str) -> Option<f32> {
+    // microseconds despite the name
+    let micros = line.strip_prefix("out_time_ms=")?.parse::<i64>().ok()?;
+    Some(micros.max(0) as f32 / 1_000_000.0)
+}
+
+/// bar of `seconds` of output, below the others by `position`
+pub fn seconds_bar(desc: impl Into<String>, seconds: f32, position: u16) -> Bar {
+    tqdm!(
+        total = seconds.ceil() as usize,
+        desc = desc.into(),
+        unit = "s",
+        position = position,
+        leave = position == 0
+    )
+}
+
+/// moves `bar` to `seconds`, ignoring terminal errors
+pub fn update_seconds(bar: &mut Bar, seconds: f32) {
+    let _ = bar.update_to((seconds as usize).min(bar.total));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::progress_seconds;
+
+    #[test]
+    fn test_progress_seconds() {
+        assert_eq!(progress_seconds("out_time_ms=12500000"), Some(12.5));
+        assert_eq!(progress_seconds("out_time_ms=N/A"), None);
+        assert_eq!(progress_seconds("out_time=00:00:12.500000"), None);
+        assert_eq!(progress_seconds("progress=end"), None);
+    }
+}
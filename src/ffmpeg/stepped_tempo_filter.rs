@@ -1,70 +1,218 @@
+use super::{
+    encoder::MIX_SAMPLE_RATE,
+    filter_graph::{Filter, FilterGraph, Pad},
+    has_filter,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
 fn lerp(from: f32, to: f32, t: f32) -> f32 {
     from + (to - from) * t
 }
 
+/// how a track is stretched to the mix tempo
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TempoMode {
+    /// keeps the pitch
+    #[default]
+    Atempo,
+    /// keeps the pitch with rubberband, for larger changes
+    #[serde(alias = "rubberband")]
+    #[value(alias = "rubberband")]
+    Keylock,
+    /// resamples like a turntable, the pitch follows the tempo
+    Vinyl,
+}
+
+impl TempoMode {
+    /// `scale` as rendered, vinyl rates are whole samples per second
+    pub fn scale(self, scale: f32) -> f32 {
+        match self {
+            Self::Vinyl => (MIX_SAMPLE_RATE as f32 * scale).round() / MIX_SAMPLE_RATE as f32,
+            _ => scale,
+        }
+    }
+
+    /// semitones the key moves by at `scale`
+    pub fn key_shift(self, scale: f32) -> f32 {
+        match self {
+            Self::Vinyl => 12.0 * self.scale(scale).log2(),
+            _ => 0.0,
+        }
+    }
+
+    pub fn filters(self, scale: f32) -> Vec<Filter> {
+        match self {
+            Self::Atempo => vec![Filter::new("atempo").arg("tempo", scale)],
+            Self::Keylock => vec![Filter::new("rubberband").arg("tempo", scale)],
+            // from a known rate whatever came before
+            Self::Vinyl => vec![
+                Filter::new("aresample").arg("osr", MIX_SAMPLE_RATE),
+                Filter::new("asetrate").arg("r", (MIX_SAMPLE_RATE as f32 * scale).round()),
+                Filter::new("aresample").arg("osr", MIX_SAMPLE_RATE),
+            ],
+        }
+    }
+}
+
+/// fails before rendering when ffmpeg lacks the filter of one of `modes`
+pub fn check_tempo_modes(mut modes: impl Iterator<Item = TempoMode>) -> Result<()> {
+    if modes.any(|mode| mode == TempoMode::Keylock) && !has_filter("rubberband")? {
+        anyhow::bail!(
+            "keylock needs ffmpeg built with librubberband (--enable-librubberband), \
+            use the atempo or vinyl tempo mode instead"
+        );
+    }
+    Ok(())
+}
+
+/// stretched spans of the input, `(begin, end, scale)` in input seconds
 #[derive(Debug)]
 pub struct SteppedTempoFilter {
     spans: Vec<(f32, f32, f32)>,
+    mode: TempoMode,
 }
 
 impl SteppedTempoFilter {
-    pub fn new(from: (f32, f32), to: (f32, f32), steps: usize) -> Self {
-        let mut spans = vec![];
-        for i in 1..=steps {
-            let begin = lerp(from.0, to.0, (i - 1) as f32 / steps as f32);
-            let end = lerp(from.0, to.0, i as f32 / steps as f32);
-            let v = lerp(from.1, to.1, (i - 1) as f32 / steps as f32);
-            spans.push((begin, end, v));
+    pub fn constant(begin: f32, end: f32, scale: f32) -> Self {
+        Self {
+            spans: vec![(begin, end, scale)],
+            mode: TempoMode::default(),
         }
-        Self { spans }
     }
 
-    pub fn to_filters(&self, input: &str, output: &str) -> Vec<String> {
-        let mut i = 0;
-        let mut filters = vec![];
-        let mut src_labels = vec![];
-        let mut dst_labels = vec![];
-
-        for (begin, end, scale) in self.spans.iter() {
-            filters.push(format!(
-                "[stf_{}] atrim={}:{},asetpts=N/SR/TB [stf_{}]",
-                i + 1,
-                begin,
-                end,
-                i + 2
-            ));
-            src_labels.push(format!("[stf_{}]", i + 1));
-            filters.push(format!("[stf_{}] atempo={} [stf_{}]", i + 2, scale, i + 3));
-            dst_labels.push(format!("[stf_{}]", i + 3));
-            i += 3;
+    /// `from_scale` from `begin` to the first of `beats`, then one span per beat at
+    /// the tempo of its middle on the line to `to_scale` at the last of `beats`
+    pub fn ramp(begin: f32, beats: &[f32], from_scale: f32, to_scale: f32) -> Self {
+        let mut spans = vec![];
+        if let Some(&first) = beats.first().filter(|first| **first > begin) {
+            spans.push((begin, first, from_scale));
         }
-        filters.insert(
-            0,
-            format!(
-                "[{}]asplit={} {}",
-                input,
-                self.spans.len(),
-                src_labels.join("")
-            ),
+        let steps = beats.len().saturating_sub(1);
+        for (i, beat) in beats.windows(2).enumerate() {
+            let t = (i as f32 + 0.5) / steps as f32;
+            spans.push((beat[0], beat[1], lerp(from_scale, to_scale, t)));
+        }
+        Self {
+            spans,
+            mode: TempoMode::default(),
+        }
+    }
+
+    pub fn mode(self, mode: TempoMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    /// semitones the key moves by at the first and the last span
+    pub fn key_shift(&self) -> (f32, f32) {
+        let shift = |span: Option<&(f32, f32, f32)>| {
+            span.map(|(_, _, scale)| self.mode.key_shift(*scale))
+                .unwrap_or_default()
+        };
+        (shift(self.spans.first()), shift(self.spans.last()))
+    }
+
+    /// seconds after stretching each span by its tempo
+    pub fn output_duration(&self) -> f32 {
+        self.spans
+            .iter()
+            .map(|(begin, end, scale)| (end - begin) / self.mode.scale(*scale))
+            .sum()
+    }
+
+    /// splits `input` into spans, stretches each and concatenates them
+    pub fn apply(&self, graph: &mut FilterGraph, input: Pad) -> Pad {
+        let sources = graph.add(
+            vec![input],
+            vec![Filter::new("asplit").arg("outputs", self.spans.len())],
+            self.spans.len(),
         );
-        filters.push(format!(
-            "{} concat=n={}:v=0:a=1 [{}]",
-            dst_labels.join(""),
-            dst_labels.len(),
-            output,
-        ));
-        filters
+        let spans = sources
+            .into_iter()
+            .zip(self.spans.iter())
+            .map(|(source, (begin, end, scale))| {
+                graph.chain(
+                    vec![source],
+                    [
+                        vec![
+                            Filter::new("atrim").arg("start", begin).arg("end", end),
+                            Filter::new("asetpts").arg("expr", "N/SR/TB"),
+                        ],
+                        self.mode.filters(*scale),
+                    ]
+                    .concat(),
+                )
+            })
+            .collect();
+        graph.chain(
+            spans,
+            vec![Filter::new("concat")
+                .arg("n", self.spans.len())
+                .arg("v", 0)
+                .arg("a", 1)],
+        )
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SteppedTempoFilter;
+    use super::{SteppedTempoFilter, TempoMode};
+    use crate::ffmpeg::filter_graph::{FilterGraph, Pad};
 
     #[test]
     fn test_pts_filter() {
-        let filter = SteppedTempoFilter::new((0.0, 1.0), (20.0, 1.8), 4);
-        dbg!(&filter);
-        dbg!(filter.to_filters("input", "output"));
+        let filter = SteppedTempoFilter::ramp(0.0, &[5.0, 10.0, 15.0, 20.0], 1.0, 1.75);
+        let mut graph = FilterGraph::new();
+        let out = filter.apply(&mut graph, Pad::input(0));
+        assert_eq!(out.to_string(), "[p8]");
+        assert_eq!(
+            graph.to_string(),
+            [
+                "[0:a]asplit=outputs=4[p0][p1][p2][p3]",
+                "[p0]atrim=start=0:end=5,asetpts=expr=N/SR/TB,atempo=tempo=1[p4]",
+                "[p1]atrim=start=5:end=10,asetpts=expr=N/SR/TB,atempo=tempo=1.125[p5]",
+                "[p2]atrim=start=10:end=15,asetpts=expr=N/SR/TB,atempo=tempo=1.375[p6]",
+                "[p3]atrim=start=15:end=20,asetpts=expr=N/SR/TB,atempo=tempo=1.625[p7]",
+                "[p4][p5][p6][p7]concat=n=4:v=0:a=1[p8]",
+            ]
+            .join(";")
+        );
+        assert_eq!(
+            filter.output_duration(),
+            5.0 + 5.0 / 1.125 + 5.0 / 1.375 + 5.0 / 1.625
+        );
+    }
+
+    #[test]
+    fn test_ramp_duration() {
+        assert_eq!(
+            SteppedTempoFilter::constant(0.0, 30.0, 1.5).output_duration(),
+            20.0
+        );
+        // 120 to 126 bpm over 32 beats of a 120 bpm track
+        let beats = (0..=32).map(|i| 10.0 + i as f32 * 0.5).collect::<Vec<_>>();
+        let filter = SteppedTempoFilter::ramp(0.0, &beats, 1.0, 1.05);
+        // 60 / bpm integrated over the beats
+        let exact = 10.0 + 60.0 * 32.0 / 6.0 * (126.0f32 / 120.0).ln();
+        assert!((filter.output_duration() - exact).abs() < 1e-3);
+        // all at once
+        let filter = SteppedTempoFilter::ramp(0.0, &[0.0, 0.5], 1.0, 1.05);
+        assert_eq!(filter.output_duration(), 0.5 / 1.025);
+    }
+
+    #[test]
+    fn test_vinyl() {
+        let filter = SteppedTempoFilter::constant(0.0, 10.0, 1.06).mode(TempoMode::Vinyl);
+        let mut graph = FilterGraph::new();
+        filter.apply(&mut graph, Pad::input(0));
+        assert!(graph
+            .to_string()
+            .contains("aresample=osr=44100,asetrate=r=46746,aresample=osr=44100"));
+        assert!((filter.output_duration() - 10.0 * 44100.0 / 46746.0).abs() < 1e-5);
+        let (from, to) = filter.key_shift();
+        assert!((from - 1.0088).abs() < 1e-3);
+        assert_eq!(from, to);
+        assert_eq!(TempoMode::Atempo.key_shift(1.06), 0.0);
     }
 }
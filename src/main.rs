@@ -2,6 +2,7 @@ use anyhow::Result;
 use cmds::handle_commands;
 
 mod cmds;
+mod export;
 mod ffmpeg;
 mod mixxx;
 
@@ -0,0 +1,260 @@
+use super::library::Library;
+use anyhow::Result;
+use prost::Message;
+
+const BEAT_GRID_1_VERSION: &str = "BeatGrid-1.0";
+const BEAT_GRID_2_VERSION: &str = "BeatGrid-2.0";
+const BEAT_MAP_VERSION: &str = "BeatMap-1.0";
+/// mixxx marks a downbeat every 4 beats from the first beat
+const BEATS_PER_BAR: f32 = 4.0;
+
+/// messages of mixxx `src/proto/beats.proto`
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Beat {
+        #[prost(int32, optional, tag = "1")]
+        pub frame_position: Option<i32>,
+        #[prost(bool, optional, tag = "2", default = "true")]
+        pub enabled: Option<bool>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Bpm {
+        #[prost(double, optional, tag = "1")]
+        pub bpm: Option<f64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BeatMap {
+        #[prost(message, repeated, tag = "1")]
+        pub beat: Vec<Beat>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BeatGrid {
+        #[prost(message, optional, tag = "1")]
+        pub bpm: Option<Bpm>,
+        #[prost(message, optional, tag = "2")]
+        pub first_beat: Option<Beat>,
+    }
+}
+
+/// tempo from `seconds` on, `beat` is the beat index there
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMarker {
+    pub seconds: f32,
+    pub bpm: f32,
+    pub beat: i64,
+}
+
+/// beat positions of a track in seconds
+#[derive(Debug, Clone, PartialEq)]
+pub enum BeatGrid {
+    /// constant tempo starting at `first_beat` seconds
+    Constant { bpm: f32, first_beat: f32 },
+    /// seconds of each beat, ascending
+    Map { beats: Vec<f32> },
+}
+
+impl BeatGrid {
+    pub fn constant(bpm: f32, first_beat: f32) -> Self {
+        Self::Constant { bpm, first_beat }
+    }
+
+    /// decodes `library.beats` blob. positions are stored in frames
+    pub fn decode(version: &str, blob: &[u8], samplerate: usize) -> Result<Self> {
+        let samplerate = samplerate as f32;
+        match version {
+            BEAT_GRID_1_VERSION => {
+                // raw `struct { double bpm; double first_beat; }` with first_beat in samples
+                if blob.len() != 16 {
+                    anyhow::bail!("invalid {} blob", version);
+                }
+                let bpm = f64::from_le_bytes(blob[0..8].try_into()?);
+                let first_beat = f64::from_le_bytes(blob[8..16].try_into()?);
+                Ok(Self::constant(
+                    bpm as f32,
+                    first_beat as f32 / 2.0 / samplerate,
+                ))
+            }
+            BEAT_GRID_2_VERSION => {
+                let grid = proto::BeatGrid::decode(blob)?;
+                let bpm = grid
+                    .bpm
+                    .and_then(|bpm| bpm.bpm)
+                    .ok_or(anyhow::anyhow!("beatgrid has no bpm"))?;
+                let first_beat = grid
+                    .first_beat
+                    .and_then(|beat| beat.frame_position)
+                    .unwrap_or(0);
+                Ok(Self::constant(bpm as f32, first_beat as f32 / samplerate))
+            }
+            BEAT_MAP_VERSION => {
+                let map = proto::BeatMap::decode(blob)?;
+                let beats = map
+                    .beat
+                    .iter()
+                    .filter(|beat| beat.enabled())
+                    .filter_map(|beat| beat.frame_position)
+                    .map(|frame| frame as f32 / samplerate)
+                    .collect::<Vec<_>>();
+                if beats.len() < 2 {
+                    anyhow::bail!("beatmap has less than 2 beats");
+                }
+                Ok(Self::Map { beats })
+            }
+            _ => anyhow::bail!("unsupported beats version {}", version),
+        }
+    }
+
+    /// falls back to a constant grid from 0s when the track is not analyzed
+    pub fn from_library(library: &Library) -> Result<Self> {
+        match (&library.beats_version, &library.beats) {
+            (Some(version), Some(blob)) => Self::decode(version, blob, library.samplerate),
+            _ => Ok(Self::constant(library.bpm, 0.0)),
+        }
+    }
+
+    /// returns beat index (fractional) at seconds
+    pub fn beat_at(&self, seconds: f32) -> f32 {
+        match self {
+            Self::Constant { bpm, first_beat } => (seconds - first_beat) * bpm / 60.0,
+            Self::Map { beats } => {
+                // index of the interval `beats[i]..beats[i + 1]`, extrapolated at both ends
+                let i = beats
+                    .partition_point(|beat| *beat <= seconds)
+                    .clamp(1, beats.len() - 1)
+                    - 1;
+                let (begin, end) = (beats[i], beats[i + 1]);
+                i as f32 + (seconds - begin) / (end - begin)
+            }
+        }
+    }
+
+    /// returns seconds at beat index (fractional)
+    pub fn seconds_at(&self, beat: f32) -> f32 {
+        match self {
+            Self::Constant { bpm, first_beat } => first_beat + beat * 60.0 / bpm,
+            Self::Map { beats } => {
+                let i = (beat.floor().max(0.0) as usize).min(beats.len() - 2);
+                let (begin, end) = (beats[i], beats[i + 1]);
+                begin + (beat - i as f32) * (end - begin)
+            }
+        }
+    }
+
+    /// moves seconds by beats along the grid, keeping the phase
+    pub fn shift(&self, seconds: f32, beats: f32) -> f32 {
+        self.seconds_at(self.beat_at(seconds) + beats)
+    }
+
+    /// seconds of the downbeat nearest to seconds
+    pub fn nearest_downbeat(&self, seconds: f32) -> f32 {
+        let bar = (self.beat_at(seconds) / BEATS_PER_BAR).round();
+        self.seconds_at(bar * BEATS_PER_BAR)
+    }
+
+    /// tempo changes from the first beat at or after 0s
+    pub fn tempo_markers(&self) -> Vec<TempoMarker> {
+        let first = self.beat_at(0.0).ceil();
+        match self {
+            Self::Constant { bpm, .. } => vec![TempoMarker {
+                seconds: self.seconds_at(first),
+                bpm: *bpm,
+                beat: first as i64,
+            }],
+            Self::Map { beats } => {
+                let mut markers: Vec<TempoMarker> = vec![];
+                for i in (first.max(0.0) as usize)..beats.len() {
+                    let interval =
+                        beats[(i + 1).min(beats.len() - 1)] - beats[i.min(beats.len() - 2)];
+                    let bpm = 60.0 / interval;
+                    if markers.last().is_some_and(|m| (m.bpm - bpm).abs() < 0.01) {
+                        continue;
+                    }
+                    markers.push(TempoMarker {
+                        seconds: beats[i],
+                        bpm,
+                        beat: i as i64,
+                    });
+                }
+                markers
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{proto, BeatGrid, TempoMarker};
+    use prost::Message;
+
+    #[test]
+    fn test_decode_beat_grid() {
+        let grid = proto::BeatGrid {
+            bpm: Some(proto::Bpm { bpm: Some(120.0) }),
+            first_beat: Some(proto::Beat {
+                frame_position: Some(22050),
+                enabled: None,
+            }),
+        };
+        let grid = BeatGrid::decode("BeatGrid-2.0", &grid.encode_to_vec(), 44100).unwrap();
+        assert_eq!(grid, BeatGrid::constant(120.0, 0.5));
+        assert_eq!(grid.beat_at(1.5), 2.0);
+        assert_eq!(grid.seconds_at(4.0), 2.5);
+        assert_eq!(grid.nearest_downbeat(2.4), 2.5);
+        assert_eq!(grid.nearest_downbeat(1.4), 0.5);
+        assert_eq!(
+            BeatGrid::constant(120.0, -0.75).tempo_markers(),
+            vec![TempoMarker {
+                seconds: 0.25,
+                bpm: 120.0,
+                beat: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn test_decode_legacy_beat_grid() {
+        let blob = [128.0f64.to_le_bytes(), 88200.0f64.to_le_bytes()].concat();
+        let grid = BeatGrid::decode("BeatGrid-1.0", &blob, 44100).unwrap();
+        assert_eq!(grid, BeatGrid::constant(128.0, 1.0));
+    }
+
+    #[test]
+    fn test_decode_beat_map() {
+        let beat = |frame_position, enabled| proto::Beat {
+            frame_position: Some(frame_position),
+            enabled: Some(enabled),
+        };
+        let map = proto::BeatMap {
+            beat: vec![
+                beat(100, true),
+                beat(200, true),
+                beat(250, false),
+                beat(300, true),
+                beat(500, true),
+            ],
+        };
+        let grid = BeatGrid::decode("BeatMap-1.0", &map.encode_to_vec(), 100).unwrap();
+        assert_eq!(
+            grid,
+            BeatGrid::Map {
+                beats: vec![1.0, 2.0, 3.0, 5.0]
+            }
+        );
+        assert_eq!(grid.beat_at(4.0), 2.5);
+        assert_eq!(grid.seconds_at(2.5), 4.0);
+        // extrapolated from the first and last intervals
+        assert_eq!(grid.beat_at(0.0), -1.0);
+        assert_eq!(grid.seconds_at(4.0), 7.0);
+        assert_eq!(grid.shift(1.5, 2.0), 4.0);
+        assert_eq!(
+            grid.tempo_markers()
+                .iter()
+                .map(|m| (m.seconds, m.bpm, m.beat))
+                .collect::<Vec<_>>(),
+            vec![(1.0, 60.0, 0), (3.0, 30.0, 2)]
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use super::repo::{AsRepo, Repo};
+use anyhow::Result;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrateTrack {
+    pub crate_id: i32,
+    pub track_id: i32,
+}
+
+impl<'a> AsRepo<'a> for CrateTrack {
+    fn repo(conn: &'a rusqlite::Connection) -> Repo<'a, Self> {
+        Repo::new(conn, "crate_tracks")
+    }
+}
+
+impl<'a> Repo<'a, CrateTrack> {
+    /// crate_tracks has no position column, so tracks are returned in insertion order
+    pub fn find_by_crate_id(&self, crate_id: i32) -> Result<Vec<CrateTrack>> {
+        let mut stmt = self.conn.prepare(
+            format!(
+                "SELECT * FROM {} WHERE crate_id=?1 ORDER BY rowid ASC",
+                self.table
+            )
+            .as_str(),
+        )?;
+        self.query(&mut stmt, params![crate_id])
+    }
+}
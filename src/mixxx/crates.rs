@@ -0,0 +1,18 @@
+use super::repo::{AsRepo, Repo};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Crate {
+    pub id: i32,
+    pub name: String,
+    count: i32,
+    show: i32,
+    locked: bool,
+    autodj_source: i32,
+}
+
+impl<'a> AsRepo<'a> for Crate {
+    fn repo(conn: &'a rusqlite::Connection) -> Repo<'a, Self> {
+        Repo::new(conn, "crates")
+    }
+}
@@ -25,7 +25,19 @@ pub struct Cue {
     pub r#type: CueType,
     pub position: f32,
     pub length: f32,
-    pub hotcue: u8,
+    /// -1 if not a hot cue
+    pub hotcue: i32,
+}
+
+impl Cue {
+    /// positions are stored in stereo samples
+    pub fn seconds(&self, samplerate: usize) -> f32 {
+        self.position / samplerate as f32 / 2.0
+    }
+
+    pub fn length_seconds(&self, samplerate: usize) -> f32 {
+        self.length / samplerate as f32 / 2.0
+    }
 }
 
 impl<'a> AsRepo<'a> for Cue {
@@ -35,6 +47,17 @@ impl<'a> AsRepo<'a> for Cue {
 }
 
 impl<'a> Repo<'a, Cue> {
+    pub fn find_by_track_id(&self, track_id: i32) -> Result<Vec<Cue>> {
+        let mut stmt = self.conn.prepare(
+            format!(
+                "SELECT * FROM {} WHERE track_id=?1 ORDER BY position ASC",
+                self.table
+            )
+            .as_str(),
+        )?;
+        self.query(&mut stmt, params![track_id])
+    }
+
     pub fn hot_cues_by_track_id(&self, track_id: i32) -> Result<Vec<Cue>> {
         let mut stmt = self.conn.prepare(
             format!("SELECT * FROM {} WHERE track_id=?1 AND type=?2", self.table).as_str(),
@@ -42,7 +65,7 @@ impl<'a> Repo<'a, Cue> {
         self.query(&mut stmt, params![track_id, CueType::HotCue as u8])
     }
 
-    pub fn hot_cue_by_track_id(&self, track_id: i32, hotcue: u8) -> Result<Cue> {
+    pub fn hot_cue_by_track_id(&self, track_id: i32, hotcue: u8) -> Result<Option<Cue>> {
         let mut stmt = self.conn.prepare(
             format!(
                 "SELECT * FROM {} WHERE track_id=?1 AND type=?2 AND hotcue=?3",
@@ -50,7 +73,45 @@ impl<'a> Repo<'a, Cue> {
             )
             .as_str(),
         )?;
-        self.query(&mut stmt, params![track_id, CueType::HotCue as u8, hotcue])
-            .and_then(|res| res.get(0).cloned().ok_or(anyhow::anyhow!("not found")))
+        let cues = self.query(&mut stmt, params![track_id, CueType::HotCue as u8, hotcue])?;
+        Ok(cues.first().cloned())
+    }
+
+    /// `cue.id` is ignored, returns the new id
+    pub fn insert(&self, cue: &Cue) -> Result<i32> {
+        self.conn.execute(
+            format!(
+                "INSERT INTO {} (track_id, type, position, length, hotcue) \
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                self.table
+            )
+            .as_str(),
+            params![
+                cue.track_id,
+                cue.r#type.clone() as u8,
+                cue.position,
+                cue.length,
+                cue.hotcue,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    pub fn update(&self, cue: &Cue) -> Result<()> {
+        self.conn.execute(
+            format!(
+                "UPDATE {} SET type=?1, position=?2, length=?3, hotcue=?4 WHERE id=?5",
+                self.table
+            )
+            .as_str(),
+            params![
+                cue.r#type.clone() as u8,
+                cue.position,
+                cue.length,
+                cue.hotcue,
+                cue.id,
+            ],
+        )?;
+        Ok(())
     }
 }
@@ -0,0 +1,244 @@
+use super::library::Library;
+use anyhow::Result;
+use prost::Message;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt::Display, str::FromStr};
+
+const MAJOR_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B",
+];
+const MINOR_NAMES: [&str; 12] = [
+    "C", "C#", "D", "Eb", "E", "F", "F#", "G", "G#", "A", "Bb", "B",
+];
+
+/// messages of mixxx `src/proto/keys.proto`
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct KeyMap {
+        /// `ChromaticKey`
+        #[prost(int32, optional, tag = "1")]
+        pub global_key: Option<i32>,
+        #[prost(string, optional, tag = "4")]
+        pub global_key_text: Option<String>,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum KeyNotation {
+    /// 1d .. 12d, 1m .. 12m
+    OpenKey,
+    /// 1A .. 12A, 1B .. 12B (Camelot)
+    Lancelot,
+    /// C, Am, F#m ...
+    Traditional,
+}
+
+/// musical key. serialized in Lancelot notation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    /// pitch class of the tonic, C = 0
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+impl Key {
+    pub fn new(tonic: u8, mode: Mode) -> Self {
+        Self {
+            tonic: tonic % 12,
+            mode,
+        }
+    }
+
+    /// mixxx `ChromaticKey`: 1 = C major .. 12 = B major, 13 = C minor .. 24 = B minor
+    pub fn from_chromatic_key(id: i32) -> Option<Self> {
+        match id {
+            1..=12 => Some(Self::new((id - 1) as u8, Mode::Major)),
+            13..=24 => Some(Self::new((id - 13) as u8, Mode::Minor)),
+            _ => None,
+        }
+    }
+
+    /// key_id, then the `keys` blob, then the `key` text
+    pub fn from_library(library: &Library) -> Result<Option<Self>> {
+        if let Some(key) = library.key_id.and_then(Self::from_chromatic_key) {
+            return Ok(Some(key));
+        }
+        if let Some(blob) = &library.keys {
+            let map = proto::KeyMap::decode(blob.as_slice())?;
+            if let Some(key) = map.global_key.and_then(Self::from_chromatic_key) {
+                return Ok(Some(key));
+            }
+            if let Some(key) = map.global_key_text.and_then(|text| text.parse().ok()) {
+                return Ok(Some(key));
+            }
+        }
+        Ok(library.key.as_ref().and_then(|text| text.parse().ok()))
+    }
+
+    /// number on the Camelot wheel, 1..=12
+    pub fn wheel_number(self) -> u8 {
+        // relative major shares the number with its minor
+        let major_tonic = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % 12,
+        };
+        // C major = 8, each fifth up is +1
+        (major_tonic * 7 + 7) % 12 + 1
+    }
+
+    fn from_wheel_number(number: u8, mode: Mode) -> Option<Self> {
+        if !(1..=12).contains(&number) {
+            return None;
+        }
+        // inverse of `wheel_number`, 7 is its own inverse mod 12
+        let major_tonic = ((number + 4) * 7) % 12;
+        let tonic = match mode {
+            Mode::Major => major_tonic,
+            Mode::Minor => (major_tonic + 9) % 12,
+        };
+        Some(Self::new(tonic, mode))
+    }
+
+    /// steps on the Camelot wheel, switching between relative major/minor is 1 step
+    pub fn camelot_distance(self, other: Key) -> u8 {
+        let diff = (self.wheel_number() + 12 - other.wheel_number()) % 12;
+        let mode_diff = if self.mode == other.mode { 0 } else { 1 };
+        diff.min(12 - diff) + mode_diff
+    }
+
+    pub fn to_notation(self, notation: KeyNotation) -> String {
+        match notation {
+            KeyNotation::OpenKey => {
+                let number = (self.wheel_number() + 4) % 12 + 1;
+                match self.mode {
+                    Mode::Major => format!("{}d", number),
+                    Mode::Minor => format!("{}m", number),
+                }
+            }
+            KeyNotation::Lancelot => match self.mode {
+                Mode::Major => format!("{}B", self.wheel_number()),
+                Mode::Minor => format!("{}A", self.wheel_number()),
+            },
+            KeyNotation::Traditional => match self.mode {
+                Mode::Major => MAJOR_NAMES[self.tonic as usize].to_string(),
+                Mode::Minor => format!("{}m", MINOR_NAMES[self.tonic as usize]),
+            },
+        }
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_notation(KeyNotation::Lancelot))
+    }
+}
+
+impl FromStr for Key {
+    type Err = anyhow::Error;
+
+    /// accepts OpenKey, Lancelot and traditional notation
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let not_a_key = || anyhow::anyhow!("invalid key {}", s);
+
+        let wheel = Regex::new(r"^(\d{1,2})([ABabdmDM])$")?;
+        if let Some(caps) = wheel.captures(s) {
+            let number: u8 = caps[1].parse()?;
+            return match &caps[2] {
+                "A" | "a" => Self::from_wheel_number(number, Mode::Minor),
+                "B" | "b" => Self::from_wheel_number(number, Mode::Major),
+                // OpenKey 1 = Lancelot 8
+                "m" | "M" => Self::from_wheel_number((number + 6) % 12 + 1, Mode::Minor),
+                _ => Self::from_wheel_number((number + 6) % 12 + 1, Mode::Major),
+            }
+            .ok_or_else(not_a_key);
+        }
+
+        let traditional = Regex::new(r"^([A-Ga-g])([#♯b♭]?)\s*(m|min|minor|maj|major)?$")?;
+        let caps = traditional.captures(s).ok_or_else(not_a_key)?;
+        let natural = match caps[1].to_ascii_uppercase().as_str() {
+            "C" => 0,
+            "D" => 2,
+            "E" => 4,
+            "F" => 5,
+            "G" => 7,
+            "A" => 9,
+            _ => 11,
+        };
+        let tonic = match caps.get(2).map(|m| m.as_str()) {
+            Some("#") | Some("♯") => natural + 1,
+            Some("b") | Some("♭") => natural + 11,
+            _ => natural,
+        };
+        let mode = match caps.get(3).map(|m| m.as_str()) {
+            Some("m") | Some("min") | Some("minor") => Mode::Minor,
+            _ => Mode::Major,
+        };
+        Ok(Self::new(tonic, mode))
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Key, KeyNotation, Mode};
+
+    #[test]
+    fn test_key_notation() {
+        let cases = [
+            (Key::new(0, Mode::Major), "1d", "8B", "C"),
+            (Key::new(9, Mode::Minor), "1m", "8A", "Am"),
+            (Key::new(7, Mode::Major), "2d", "9B", "G"),
+            (Key::new(11, Mode::Major), "6d", "1B", "B"),
+            (Key::new(8, Mode::Minor), "6m", "1A", "G#m"),
+            (Key::new(5, Mode::Major), "12d", "7B", "F"),
+            (Key::new(1, Mode::Major), "8d", "3B", "Db"),
+        ];
+        for (key, open_key, lancelot, traditional) in cases {
+            assert_eq!(key.to_notation(KeyNotation::OpenKey), open_key);
+            assert_eq!(key.to_notation(KeyNotation::Lancelot), lancelot);
+            assert_eq!(key.to_notation(KeyNotation::Traditional), traditional);
+            assert_eq!(open_key.parse::<Key>().unwrap(), key);
+            assert_eq!(lancelot.parse::<Key>().unwrap(), key);
+            assert_eq!(traditional.parse::<Key>().unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!("Abm".parse::<Key>().unwrap(), Key::new(8, Mode::Minor));
+        assert_eq!("C# minor".parse::<Key>().unwrap(), Key::new(1, Mode::Minor));
+        assert_eq!("E♭".parse::<Key>().unwrap(), Key::new(3, Mode::Major));
+        assert!("13A".parse::<Key>().is_err());
+        assert!("H".parse::<Key>().is_err());
+        assert_eq!(Key::from_chromatic_key(22), Some(Key::new(9, Mode::Minor)));
+    }
+
+    #[test]
+    fn test_camelot_distance() {
+        let key = |s: &str| s.parse::<Key>().unwrap();
+        assert_eq!(key("8A").camelot_distance(key("8A")), 0);
+        assert_eq!(key("8A").camelot_distance(key("8B")), 1);
+        assert_eq!(key("12A").camelot_distance(key("1A")), 1);
+        assert_eq!(key("3A").camelot_distance(key("9B")), 7);
+    }
+}
@@ -6,21 +6,28 @@ pub struct Library {
     pub id: i32,
     pub artist: Option<String>,
     pub title: String,
-    album: Option<String>,
-    year: Option<String>,
-    genre: Option<String>,
-    tracknumber: Option<String>,
-    location: f32,
-    comment: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<String>,
+    pub genre: Option<String>,
+    pub tracknumber: Option<String>,
+    /// track_locations.id
+    pub location: i32,
+    pub comment: Option<String>,
     /// seconds
     pub duration: f32,
-    bitrate: f32,
+    pub bitrate: f32,
     pub samplerate: usize,
+    /// 0 when unknown
+    pub channels: i32,
     // dB = 20 * log10 replaygain
     pub replaygain: f32,
     pub bpm: f32,
-    key: Option<String>,
-    rating: i32,
+    pub key: Option<String>,
+    pub key_id: Option<i32>,
+    pub keys: Option<Vec<u8>>,
+    pub rating: i32,
+    pub beats: Option<Vec<u8>>,
+    pub beats_version: Option<String>,
 }
 
 impl<'a> AsRepo<'a> for Library {
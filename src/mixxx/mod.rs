@@ -1,4 +1,8 @@
+pub mod beats;
+pub mod crate_track;
+pub mod crates;
 pub mod cue;
+pub mod key;
 pub mod library;
 pub mod playlist;
 pub mod playlist_track;
@@ -2,18 +2,20 @@ use super::{
     repo::{AsRepo, Repo},
     serde_datetime,
 };
+use anyhow::Result;
 use chrono::NaiveDateTime;
+use rusqlite::params;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Playlist {
-    id: i32,
+    pub id: i32,
     pub name: String,
     position: usize,
     hidden: i32,
     #[serde(with = "serde_datetime")]
     date_created: NaiveDateTime,
-    locked: bool,
+    pub locked: bool,
 }
 
 impl<'a> AsRepo<'a> for Playlist {
@@ -21,3 +23,40 @@ impl<'a> AsRepo<'a> for Playlist {
         Repo::new(conn, "Playlists")
     }
 }
+
+impl<'a> Repo<'a, Playlist> {
+    /// appends a visible playlist and returns its id
+    pub fn insert(&self, name: &str) -> Result<i32> {
+        self.conn.execute(
+            format!(
+                "INSERT INTO {0} (name, position, hidden, date_created, date_modified, locked) \
+                VALUES (?1, (SELECT COALESCE(MAX(position), 0) + 1 FROM {0}), 0, \
+                datetime('now'), datetime('now'), 0)",
+                self.table
+            )
+            .as_str(),
+            params![name],
+        )?;
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    pub fn update(&self, playlist: &Playlist) -> Result<()> {
+        self.conn.execute(
+            format!(
+                "UPDATE {} SET name=?1, locked=?2, date_modified=datetime('now') WHERE id=?3",
+                self.table
+            )
+            .as_str(),
+            params![playlist.name, playlist.locked, playlist.id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete(&self, id: i32) -> Result<()> {
+        self.conn.execute(
+            format!("DELETE FROM {} WHERE id=?1", self.table).as_str(),
+            params![id],
+        )?;
+        Ok(())
+    }
+}
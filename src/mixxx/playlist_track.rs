@@ -28,4 +28,55 @@ impl<'a> Repo<'a, PlaylistTrack> {
         )?;
         self.query(&mut stmt, params![playlist_id])
     }
+
+    /// `playlist_track.id` is ignored, returns the new id
+    pub fn insert(&self, playlist_track: &PlaylistTrack) -> Result<i32> {
+        self.conn.execute(
+            format!(
+                "INSERT INTO {} (playlist_id, track_id, position, pl_datetime_added) \
+                VALUES (?1, ?2, ?3, datetime('now'))",
+                self.table
+            )
+            .as_str(),
+            params![
+                playlist_track.playlist_id,
+                playlist_track.track_id,
+                playlist_track.position,
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid() as i32)
+    }
+
+    pub fn update(&self, playlist_track: &PlaylistTrack) -> Result<()> {
+        self.conn.execute(
+            format!(
+                "UPDATE {} SET playlist_id=?1, track_id=?2, position=?3 WHERE id=?4",
+                self.table
+            )
+            .as_str(),
+            params![
+                playlist_track.playlist_id,
+                playlist_track.track_id,
+                playlist_track.position,
+                playlist_track.id,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete(&self, id: i32) -> Result<()> {
+        self.conn.execute(
+            format!("DELETE FROM {} WHERE id=?1", self.table).as_str(),
+            params![id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_by_playlist_id(&self, playlist_id: i32) -> Result<()> {
+        self.conn.execute(
+            format!("DELETE FROM {} WHERE playlist_id=?1", self.table).as_str(),
+            params![playlist_id],
+        )?;
+        Ok(())
+    }
 }
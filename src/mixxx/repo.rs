@@ -46,6 +46,6 @@ impl<'a, T: for<'de> Deserialize<'de> + Clone> Repo<'a, T> {
             .conn
             .prepare(format!("SELECT * FROM {} WHERE id=?1", self.table).as_str())?;
         let items = self.query(&mut stmt, params![id])?;
-        Ok(items.get(0).cloned())
+        Ok(items.first().cloned())
     }
 }